@@ -0,0 +1,36 @@
+//! worker 线程的 CPU 亲和性
+//! 目前只在 Linux 上绑定，其他平台上绑定请求被忽略，线程仍由系统自由调度
+
+use log::warn;
+
+// 当前进程可以使用的 CPU 编号
+#[cfg(target_os = "linux")]
+pub(crate) fn core_ids() -> Vec<usize> {
+    match core_affinity::get_core_ids() {
+        Some(ids) if !ids.is_empty() => ids.into_iter().map(|core| core.id).collect(),
+        _ => fallback_core_ids(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn core_ids() -> Vec<usize> {
+    fallback_core_ids()
+}
+
+fn fallback_core_ids() -> Vec<usize> {
+    let num = std::thread::available_parallelism().map(|num| num.get()).unwrap_or(1);
+    (0..num).collect()
+}
+
+// 把调用线程绑定到指定 CPU，失败时只打印警告
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current(core: usize) {
+    if !core_affinity::set_for_current(core_affinity::CoreId { id: core }) {
+        warn!("failed to pin {:?} to cpu {}", std::thread::current().name(), core);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current(core: usize) {
+    warn!("cpu affinity is only supported on linux, {:?} is not pinned to cpu {}", std::thread::current().name(), core);
+}
@@ -0,0 +1,225 @@
+//! 阻塞任务线程池
+//! CPU 密集或会阻塞的调用放到独立的线程池执行，避免占住 High/Low worker；
+//! 线程按需创建，空闲超过 keep_alive 后退出
+
+use crate::multi_worker_queue::Handle;
+use crate::sim::{self, ExternalOp};
+use crate::task::{next_task_id, AbortState, JoinHandle};
+use async_task::Runnable;
+use std::collections::VecDeque;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// 空闲线程等待新任务的最长时间
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+// 排队中的阻塞任务；在模拟中 spawn 的任务带有 ExternalOp，执行完（结果已经唤醒等待方）后才释放
+struct Job {
+    runnable: Runnable,
+    external: Option<ExternalOp>,
+}
+
+struct PoolState {
+    queue: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    // 已经 notify 但还没醒来的空闲线程数，它们不能再分配给新任务
+    notified: usize,
+    // 已经被线程取出、正在执行的任务数
+    active: usize,
+    shutdown: bool,
+}
+
+pub(crate) struct BlockingPool {
+    state: Mutex<PoolState>,
+    condvar: Condvar,
+    max_threads: usize,
+}
+
+// 不在运行时 worker 线程上调用 spawn_blocking 时使用的默认线程池
+static DEFAULT_POOL: LazyLock<Arc<BlockingPool>> = LazyLock::new(|| BlockingPool::new(512));
+
+impl BlockingPool {
+    pub(crate) fn new(max_threads: usize) -> Arc<Self> {
+        Arc::new(BlockingPool {
+            state: Mutex::new(PoolState {
+                queue: VecDeque::new(),
+                threads: 0,
+                idle: 0,
+                notified: 0,
+                active: 0,
+                shutdown: false,
+            }),
+            condvar: Condvar::new(),
+            max_threads: max_threads.max(1),
+        })
+    }
+
+    pub(crate) fn spawn<F, T>(self: &Arc<Self>, f: F) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static
+    {
+        let pool = self.clone();
+        // 阻塞闭包只会被 poll 一次，Runnable 直接交给线程池；panic 同样作为 JoinError 返回，
+        // 开始执行前被 abort 的闭包不会再运行
+        let abort = AbortState::new();
+        let state = abort.clone();
+        let future = async move { catch_unwind(AssertUnwindSafe(|| (!state.is_aborted()).then(f))) };
+        let (runnable, task) = async_task::spawn(future, move |runnable| pool.schedule(Job { runnable, external: None }));
+        self.schedule(Job { runnable, external: sim::external_op() });
+        JoinHandle::new(task, abort, next_task_id())
+    }
+
+    fn schedule(self: &Arc<Self>, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        if state.shutdown {
+            // 线程池已关闭：丢弃 Runnable，对应的 JoinHandle 得到 JoinError::Cancelled
+            return;
+        }
+        state.queue.push_back(job);
+
+        // 有尚未被唤醒的空闲线程就唤醒一个，否则在上限内新建线程；
+        // 连续 spawn 时不能反复 notify 同一个还没醒来的线程，否则任务会串行执行
+        if state.idle > state.notified {
+            state.notified += 1;
+            self.condvar.notify_one();
+        } else if state.threads < self.max_threads {
+            state.threads += 1;
+            let pool = self.clone();
+            let spawned = thread::Builder::new()
+                .name("blocking".to_string())
+                .spawn(move || pool.run());
+            if spawned.is_err() {
+                state.threads -= 1;
+                log::error!("failed to spawn blocking thread, {} threads running", state.threads);
+            }
+        }
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                state.active += 1;
+                drop(state);
+                let _ = catch_unwind(|| job.runnable.run());
+                drop(job.external);
+                state = self.state.lock().unwrap();
+                state.active -= 1;
+                continue;
+            }
+            if state.shutdown {
+                break;
+            }
+
+            state.idle += 1;
+            let (guard, timeout) = self.condvar.wait_timeout(state, KEEP_ALIVE).unwrap();
+            state = guard;
+            state.idle -= 1;
+            // 超时或虚假唤醒时可能没有对应的 notify
+            state.notified = state.notified.saturating_sub(1);
+
+            if timeout.timed_out() && state.queue.is_empty() {
+                break;
+            }
+        }
+        state.threads -= 1;
+    }
+
+    // 不再接受新任务并丢弃排队中的任务；正在执行的阻塞调用无法中断，执行完后线程退出。
+    // 返回被丢弃的与仍在执行的任务数之和；为排队任务新建、还没取到任务的线程不计入
+    pub(crate) fn shutdown(&self) -> usize {
+        let (queued, running): (Vec<Job>, usize) = {
+            let mut state = self.state.lock().unwrap();
+            state.shutdown = true;
+            self.condvar.notify_all();
+            (state.queue.drain(..).collect(), state.active)
+        };
+        let pending = queued.len() + running;
+        drop(queued);
+        pending
+    }
+}
+
+// 在阻塞线程池上执行 f，返回的 JoinHandle 可以在异步任务中 await
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+    where F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static
+{
+    match Handle::try_current() {
+        Some(handle) => handle.spawn_blocking(f),
+        None => DEFAULT_POOL.spawn(f),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::future::block_on;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    // 执行期间记录同时运行的任务数峰值
+    fn tracked_job(running: &Arc<AtomicUsize>, peak: &Arc<AtomicUsize>) -> impl FnOnce() + Send + 'static {
+        let (running, peak) = (running.clone(), peak.clone());
+        move || {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn back_to_back_jobs_grow_pool_up_to_max() {
+        let pool = BlockingPool::new(2);
+        let (running, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let started = Instant::now();
+        let jobs: Vec<_> = (0..4).map(|_| pool.spawn(tracked_job(&running, &peak))).collect();
+        for job in jobs {
+            block_on(job).unwrap();
+        }
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.state.lock().unwrap().threads, 2);
+        // 两个线程各执行两个任务
+        assert!(started.elapsed() < Duration::from_millis(190));
+    }
+
+    #[test]
+    fn idle_threads_are_reused() {
+        let pool = BlockingPool::new(4);
+        let first = block_on(pool.spawn(|| thread::current().id())).unwrap();
+        // 等线程执行完回到空闲状态
+        while pool.state.lock().unwrap().idle == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let second = block_on(pool.spawn(|| thread::current().id())).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(pool.state.lock().unwrap().threads, 1);
+    }
+
+    #[test]
+    fn shutdown_counts_running_and_queued_jobs_once() {
+        let pool = BlockingPool::new(2);
+        let (started_tx, started_rx) = flume::unbounded();
+        let (release_tx, release_rx) = flume::unbounded::<()>();
+        let running = pool.spawn(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        });
+        started_rx.recv().unwrap();
+
+        // 第二个任务可能还在队列中，也可能已经被新线程取出，但只计一次
+        let queued = pool.spawn(|| ());
+        assert_eq!(pool.shutdown(), 2);
+
+        drop(release_tx);
+        block_on(running).unwrap();
+        let _ = block_on(queued);
+        assert!(block_on(pool.spawn(|| ())).unwrap_err().is_cancelled());
+    }
+}
@@ -0,0 +1,241 @@
+//! 分层取消令牌
+//! 取消父令牌会同时取消所有子令牌，任务通过 await cancelled() 感知取消，
+//! 适合让长时间运行的循环（爬虫、字幕翻译等）在合适的位置干净地退出
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+
+struct TokenState {
+    waiters: HashMap<usize, Waker>,
+    next_key: usize,
+    children: Vec<Weak<TokenInner>>,
+}
+
+struct TokenInner {
+    cancelled: AtomicBool,
+    state: Mutex<TokenState>,
+    // 子令牌强引用父令牌：中间的令牌被丢弃后仍然留在链上，父令牌的取消照样能传到孙令牌
+    parent: Option<Arc<TokenInner>>,
+}
+
+impl TokenInner {
+    fn new(parent: Option<Arc<TokenInner>>) -> Self {
+        TokenInner {
+            parent,
+            cancelled: AtomicBool::new(false),
+            state: Mutex::new(TokenState {
+                waiters: HashMap::new(),
+                next_key: 0,
+                children: Vec::new(),
+            }),
+        }
+    }
+
+    // 自己或任一祖先已被取消
+    fn is_cancelled(&self) -> bool {
+        let mut inner = Some(self);
+        while let Some(token) = inner {
+            if token.cancelled.load(Ordering::SeqCst) {
+                return true;
+            }
+            inner = token.parent.as_deref();
+        }
+        false
+    }
+
+    fn cancel(&self) {
+        let (waiters, children) = {
+            let mut state = self.state.lock().unwrap();
+            if self.cancelled.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            (std::mem::take(&mut state.waiters), std::mem::take(&mut state.children))
+        };
+
+        // 在锁外唤醒和递归取消，避免与子令牌的锁形成嵌套
+        for waker in waiters.into_values() {
+            waker.wake();
+        }
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken { inner: Arc::new(TokenInner::new(None)) }
+    }
+
+    // 子令牌随父令牌一起取消；取消子令牌不影响父令牌
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken { inner: Arc::new(TokenInner::new(Some(self.inner.clone()))) };
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            if !self.inner.cancelled.load(Ordering::SeqCst) {
+                state.children.retain(|child| child.strong_count() > 0);
+                state.children.push(Arc::downgrade(&child.inner));
+                return child;
+            }
+        }
+        child.cancel();
+        child
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+
+    // 令牌被取消时完成
+    pub fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled { token: self, key: None }
+    }
+
+    // 令牌被取消前 future 完成返回 Some，否则丢弃 future 返回 None
+    pub async fn run_until_cancelled<F: Future>(&self, future: F) -> Option<F::Output> {
+        let mut future = pin!(future);
+        let mut cancelled = self.cancelled();
+        std::future::poll_fn(|cx| {
+            if Pin::new(&mut cancelled).poll(cx).is_ready() {
+                return Poll::Ready(None);
+            }
+            future.as_mut().poll(cx).map(Some)
+        })
+        .await
+    }
+}
+
+// cancelled() 返回的 future，drop 时注销登记的 waker
+pub struct Cancelled<'a> {
+    token: &'a CancellationToken,
+    key: Option<usize>,
+}
+
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let token = self.token;
+        if token.is_cancelled() {
+            return Poll::Ready(());
+        }
+
+        let mut state = token.inner.state.lock().unwrap();
+        // 持锁后再检查一次：cancel 在持锁时设置标志，不会漏掉唤醒
+        if token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        match self.key {
+            Some(key) => {
+                if let Some(waker) = state.waiters.get_mut(&key) {
+                    if !waker.will_wake(cx.waker()) {
+                        *waker = cx.waker().clone();
+                    }
+                }
+            },
+            None => {
+                let key = state.next_key;
+                state.next_key += 1;
+                state.waiters.insert(key, cx.waker().clone());
+                drop(state);
+                self.key = Some(key);
+            },
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.token.inner.state.lock().unwrap().waiters.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::future::{block_on, poll_once};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn cancel_reaches_children_and_grandchildren() {
+        let root = CancellationToken::new();
+        let child = root.child_token();
+        // 中间的令牌被丢弃后，孙令牌仍然跟随根令牌
+        let grandchild = root.child_token().child_token();
+
+        root.cancel();
+        assert!(child.is_cancelled());
+        assert!(grandchild.is_cancelled());
+    }
+
+    #[test]
+    fn child_cancel_does_not_cancel_parent() {
+        let root = CancellationToken::new();
+        let child = root.child_token();
+        let sibling = root.child_token();
+        let grandchild = child.child_token();
+
+        child.cancel();
+        assert!(grandchild.is_cancelled());
+        assert!(!root.is_cancelled());
+        assert!(!sibling.is_cancelled());
+    }
+
+    #[test]
+    fn child_of_cancelled_token_starts_cancelled() {
+        let root = CancellationToken::new();
+        root.cancel();
+        assert!(root.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn cancel_wakes_waiters() {
+        let root = CancellationToken::new();
+        let grandchild = root.child_token().child_token();
+
+        let mut cancelled = std::pin::pin!(grandchild.cancelled());
+        assert!(block_on(poll_once(cancelled.as_mut())).is_none());
+
+        let canceller = {
+            let root = root.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                root.cancel();
+            })
+        };
+        block_on(cancelled);
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn run_until_cancelled_drops_the_future() {
+        let token = CancellationToken::new();
+        assert_eq!(block_on(token.run_until_cancelled(async { 1 })), Some(1));
+
+        token.cancel();
+        assert_eq!(block_on(token.run_until_cancelled(std::future::pending::<()>())), None);
+    }
+}
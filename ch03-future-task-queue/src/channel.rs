@@ -0,0 +1,959 @@
+//! 异步通道
+//! oneshot、mpsc（有界 / 无界）、broadcast、watch 四种通道，接收端在没有数据时登记 waker，
+//! 发送时才唤醒，不依赖 tokio，也不绑定具体运行时；
+//! 有界 mpsc 的容量由公平的 Semaphore 控制，通道满时发送方按先后顺序排队
+
+use std::collections::HashMap;
+use std::fmt;
+use std::task::Waker;
+
+// 发送失败：接收端已经全部关闭，原样交还数据
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+// 接收失败：发送端已经全部关闭，且没有剩余数据
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "channel closed")
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    // 暂时没有数据
+    Empty,
+    // 发送端已经全部关闭，且没有剩余数据
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "channel empty"),
+            TryRecvError::Disconnected => write!(f, "channel closed"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+// 多个接收端各自登记的 waker，发送时全部唤醒
+#[derive(Default)]
+struct Wakers {
+    wakers: HashMap<usize, Waker>,
+    next_id: usize,
+}
+
+impl Wakers {
+    fn next_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn register(&mut self, id: usize, waker: &Waker) {
+        match self.wakers.get_mut(&id) {
+            Some(current) if current.will_wake(waker) => {},
+            _ => {
+                self.wakers.insert(id, waker.clone());
+            },
+        }
+    }
+
+    fn remove(&mut self, id: usize) {
+        self.wakers.remove(&id);
+    }
+
+    fn take(&mut self) -> Vec<Waker> {
+        self.wakers.drain().map(|(_, waker)| waker).collect()
+    }
+}
+
+fn wake_all(wakers: Vec<Waker>) {
+    for waker in wakers {
+        waker.wake();
+    }
+}
+
+// 只发送一次的通道：接收端本身就是 future
+pub mod oneshot {
+    use super::{RecvError, TryRecvError};
+    use std::future::{poll_fn, Future};
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+
+    struct State<T> {
+        value: Option<T>,
+        sender_dropped: bool,
+        receiver_closed: bool,
+        receiver_waker: Option<Waker>,
+        // Sender::closed 等待接收端关闭
+        sender_waker: Option<Waker>,
+    }
+
+    pub struct Sender<T> {
+        state: Arc<Mutex<State<T>>>,
+    }
+
+    pub struct Receiver<T> {
+        state: Arc<Mutex<State<T>>>,
+    }
+
+    pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+        let state = Arc::new(Mutex::new(State {
+            value: None,
+            sender_dropped: false,
+            receiver_closed: false,
+            receiver_waker: None,
+            sender_waker: None,
+        }));
+        (Sender { state: state.clone() }, Receiver { state })
+    }
+
+    impl<T> Sender<T> {
+        // 接收端已经关闭时原样返回数据
+        pub fn send(self, value: T) -> Result<(), T> {
+            let mut state = self.state.lock().unwrap();
+            if state.receiver_closed {
+                return Err(value);
+            }
+            state.value = Some(value);
+            // 接收端在 Sender 的 drop 中被唤醒
+            Ok(())
+        }
+
+        pub fn is_closed(&self) -> bool {
+            self.state.lock().unwrap().receiver_closed
+        }
+
+        // 等待接收端关闭，可以用来提前放弃已经没人要的计算
+        pub async fn closed(&self) {
+            poll_fn(|cx| {
+                let mut state = self.state.lock().unwrap();
+                if state.receiver_closed {
+                    return Poll::Ready(());
+                }
+                state.sender_waker = Some(cx.waker().clone());
+                Poll::Pending
+            })
+            .await
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            let waker = {
+                let mut state = self.state.lock().unwrap();
+                state.sender_dropped = true;
+                state.receiver_waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    impl<T> Receiver<T> {
+        // 关闭后发送方的 send 失败，已经发送的数据仍然可以 try_recv 取出
+        pub fn close(&mut self) {
+            let waker = {
+                let mut state = self.state.lock().unwrap();
+                state.receiver_closed = true;
+                state.sender_waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+
+        pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+            let mut state = self.state.lock().unwrap();
+            match state.value.take() {
+                Some(value) => Ok(value),
+                None if state.sender_dropped => Err(TryRecvError::Disconnected),
+                None => Err(TryRecvError::Empty),
+            }
+        }
+    }
+
+    impl<T> Future for Receiver<T> {
+        type Output = Result<T, RecvError>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let mut state = self.state.lock().unwrap();
+            match state.value.take() {
+                Some(value) => Poll::Ready(Ok(value)),
+                None if state.sender_dropped => Poll::Ready(Err(RecvError)),
+                None => {
+                    state.receiver_waker = Some(cx.waker().clone());
+                    Poll::Pending
+                },
+            }
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            self.close();
+        }
+    }
+}
+
+// 多生产者单消费者通道
+pub mod mpsc {
+    use super::{SendError, TryRecvError};
+    use crate::sync::Semaphore;
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::future::poll_fn;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Waker};
+
+    // 接收端关闭时一次性放出足够多的许可，唤醒所有排队的发送方，让它们看到通道已关闭
+    const CLOSED_PERMITS: usize = usize::MAX >> 4;
+
+    pub enum TrySendError<T> {
+        Full(T),
+        Closed(T),
+    }
+
+    impl<T> TrySendError<T> {
+        pub fn into_inner(self) -> T {
+            match self {
+                TrySendError::Full(value) | TrySendError::Closed(value) => value,
+            }
+        }
+    }
+
+    impl<T> fmt::Debug for TrySendError<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                TrySendError::Full(_) => write!(f, "Full(..)"),
+                TrySendError::Closed(_) => write!(f, "Closed(..)"),
+            }
+        }
+    }
+
+    impl<T> fmt::Display for TrySendError<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                TrySendError::Full(_) => write!(f, "channel full"),
+                TrySendError::Closed(_) => write!(f, "channel closed"),
+            }
+        }
+    }
+
+    impl<T> std::error::Error for TrySendError<T> {}
+
+    struct State<T> {
+        queue: VecDeque<T>,
+        senders: usize,
+        closed: bool,
+        receiver_waker: Option<Waker>,
+    }
+
+    struct Chan<T> {
+        state: Mutex<State<T>>,
+        // 有界通道的剩余容量，无界通道不使用
+        permits: Semaphore,
+        bounded: bool,
+    }
+
+    impl<T> Chan<T> {
+        fn new(capacity: Option<usize>) -> Arc<Self> {
+            Arc::new(Chan {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    senders: 1,
+                    closed: false,
+                    receiver_waker: None,
+                }),
+                permits: Semaphore::new(capacity.unwrap_or(0)),
+                bounded: capacity.is_some(),
+            })
+        }
+
+        // 容量已经由调用方保证
+        fn push(&self, value: T) -> Result<(), SendError<T>> {
+            let waker = {
+                let mut state = self.state.lock().unwrap();
+                if state.closed {
+                    return Err(SendError(value));
+                }
+                state.queue.push_back(value);
+                state.receiver_waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+            Ok(())
+        }
+
+        fn is_closed(&self) -> bool {
+            self.state.lock().unwrap().closed
+        }
+
+        fn add_sender(&self) {
+            self.state.lock().unwrap().senders += 1;
+        }
+
+        fn drop_sender(&self) {
+            let waker = {
+                let mut state = self.state.lock().unwrap();
+                state.senders -= 1;
+                if state.senders > 0 {
+                    return;
+                }
+                state.receiver_waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+
+    // 有界通道的发送端
+    pub struct Sender<T> {
+        chan: Arc<Chan<T>>,
+    }
+
+    // 无界通道的发送端，send 不会等待
+    pub struct UnboundedSender<T> {
+        chan: Arc<Chan<T>>,
+    }
+
+    // 接收端，有界与无界通道共用
+    pub struct Receiver<T> {
+        chan: Arc<Chan<T>>,
+    }
+
+    // 有界通道：缓冲区满时 send 等待接收端取走数据
+    pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+        assert!(capacity > 0, "mpsc channel capacity must be non-zero");
+        let chan = Chan::new(Some(capacity));
+        (Sender { chan: chan.clone() }, Receiver { chan })
+    }
+
+    pub fn unbounded_channel<T>() -> (UnboundedSender<T>, Receiver<T>) {
+        let chan = Chan::new(None);
+        (UnboundedSender { chan: chan.clone() }, Receiver { chan })
+    }
+
+    impl<T> Sender<T> {
+        // 等待空位后发送；等待期间被取消时退出排队，数据随 future 一起丢弃
+        pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+            if self.chan.is_closed() {
+                return Err(SendError(value));
+            }
+            let permit = self.chan.permits.acquire().await;
+            self.chan.push(value)?;
+            // 位置由接收端取走数据时归还
+            permit.forget();
+            Ok(())
+        }
+
+        pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+            if self.chan.is_closed() {
+                return Err(TrySendError::Closed(value));
+            }
+            let Some(permit) = self.chan.permits.try_acquire() else {
+                return Err(TrySendError::Full(value));
+            };
+            self.chan.push(value).map_err(|SendError(value)| TrySendError::Closed(value))?;
+            permit.forget();
+            Ok(())
+        }
+
+        pub fn is_closed(&self) -> bool {
+            self.chan.is_closed()
+        }
+    }
+
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Self {
+            self.chan.add_sender();
+            Sender { chan: self.chan.clone() }
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            self.chan.drop_sender();
+        }
+    }
+
+    impl<T> UnboundedSender<T> {
+        pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+            self.chan.push(value)
+        }
+
+        pub fn is_closed(&self) -> bool {
+            self.chan.is_closed()
+        }
+    }
+
+    impl<T> Clone for UnboundedSender<T> {
+        fn clone(&self) -> Self {
+            self.chan.add_sender();
+            UnboundedSender { chan: self.chan.clone() }
+        }
+    }
+
+    impl<T> Drop for UnboundedSender<T> {
+        fn drop(&mut self) {
+            self.chan.drop_sender();
+        }
+    }
+
+    impl<T> Receiver<T> {
+        // 所有发送端关闭且数据取完后返回 None
+        pub async fn recv(&mut self) -> Option<T> {
+            poll_fn(|cx| self.poll_recv(cx)).await
+        }
+
+        pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+            let mut state = self.chan.state.lock().unwrap();
+            if let Some(value) = state.queue.pop_front() {
+                drop(state);
+                self.release();
+                return Poll::Ready(Some(value));
+            }
+            if state.senders == 0 || state.closed {
+                return Poll::Ready(None);
+            }
+            state.receiver_waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+
+        pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+            let mut state = self.chan.state.lock().unwrap();
+            match state.queue.pop_front() {
+                Some(value) => {
+                    drop(state);
+                    self.release();
+                    Ok(value)
+                },
+                None if state.senders == 0 || state.closed => Err(TryRecvError::Disconnected),
+                None => Err(TryRecvError::Empty),
+            }
+        }
+
+        // 取走一条数据后归还有界通道的一个位置
+        fn release(&self) {
+            if self.chan.bounded {
+                self.chan.permits.add_permits(1);
+            }
+        }
+
+        // 不再接受新数据，已经在缓冲区中的数据仍然可以取出
+        pub fn close(&mut self) {
+            let mut state = self.chan.state.lock().unwrap();
+            if state.closed {
+                return;
+            }
+            state.closed = true;
+            drop(state);
+            self.chan.permits.add_permits(CLOSED_PERMITS);
+        }
+
+        // 接收端已关闭或发送端已全部关闭
+        pub fn is_closed(&self) -> bool {
+            let state = self.chan.state.lock().unwrap();
+            state.closed || state.senders == 0
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            self.close();
+            // 发送端可能还持有通道，缓冲区中的数据在这里就丢弃
+            let queue = std::mem::take(&mut self.chan.state.lock().unwrap().queue);
+            drop(queue);
+        }
+    }
+}
+
+// 广播通道：每个接收端都收到发送之后的每一条数据；落后超过容量的接收端会收到 Lagged
+pub mod broadcast {
+    use super::{wake_all, SendError, Wakers};
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::future::poll_fn;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum RecvError {
+        // 所有发送端已关闭，且没有剩余数据
+        Closed,
+        // 接收端落后太多，跳过了 n 条被覆盖的数据，下一次 recv 从最旧的数据继续
+        Lagged(u64),
+    }
+
+    impl fmt::Display for RecvError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                RecvError::Closed => write!(f, "channel closed"),
+                RecvError::Lagged(skipped) => write!(f, "channel lagged by {}", skipped),
+            }
+        }
+    }
+
+    impl std::error::Error for RecvError {}
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum TryRecvError {
+        Empty,
+        Closed,
+        Lagged(u64),
+    }
+
+    impl fmt::Display for TryRecvError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                TryRecvError::Empty => write!(f, "channel empty"),
+                TryRecvError::Closed => write!(f, "channel closed"),
+                TryRecvError::Lagged(skipped) => write!(f, "channel lagged by {}", skipped),
+            }
+        }
+    }
+
+    impl std::error::Error for TryRecvError {}
+
+    struct State<T> {
+        // 环形缓冲区，front 的序号为 next_pos - buffer.len()
+        buffer: VecDeque<T>,
+        capacity: usize,
+        next_pos: u64,
+        senders: usize,
+        receivers: usize,
+        wakers: Wakers,
+    }
+
+    impl<T> State<T> {
+        fn head_pos(&self) -> u64 {
+            self.next_pos - self.buffer.len() as u64
+        }
+    }
+
+    pub struct Sender<T> {
+        state: Arc<Mutex<State<T>>>,
+    }
+
+    pub struct Receiver<T> {
+        state: Arc<Mutex<State<T>>>,
+        id: usize,
+        // 下一条要读取的数据序号
+        next: u64,
+    }
+
+    pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+        assert!(capacity > 0, "broadcast channel capacity must be non-zero");
+        let mut wakers = Wakers::default();
+        let id = wakers.next_id();
+        let state = Arc::new(Mutex::new(State {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            next_pos: 0,
+            senders: 1,
+            receivers: 1,
+            wakers,
+        }));
+        (Sender { state: state.clone() }, Receiver { state, id, next: 0 })
+    }
+
+    impl<T: Clone> Sender<T> {
+        // 返回收到这条数据的接收端数量，没有接收端时失败
+        pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+            let (receivers, wakers) = {
+                let mut state = self.state.lock().unwrap();
+                if state.receivers == 0 {
+                    return Err(SendError(value));
+                }
+                if state.buffer.len() == state.capacity {
+                    state.buffer.pop_front();
+                }
+                state.buffer.push_back(value);
+                state.next_pos += 1;
+                (state.receivers, state.wakers.take())
+            };
+            wake_all(wakers);
+            Ok(receivers)
+        }
+
+        // 新接收端只收到订阅之后发送的数据
+        pub fn subscribe(&self) -> Receiver<T> {
+            let mut state = self.state.lock().unwrap();
+            state.receivers += 1;
+            let id = state.wakers.next_id();
+            Receiver { state: self.state.clone(), id, next: state.next_pos }
+        }
+
+        pub fn receiver_count(&self) -> usize {
+            self.state.lock().unwrap().receivers
+        }
+    }
+
+    impl<T> Clone for Sender<T> {
+        fn clone(&self) -> Self {
+            self.state.lock().unwrap().senders += 1;
+            Sender { state: self.state.clone() }
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            let wakers = {
+                let mut state = self.state.lock().unwrap();
+                state.senders -= 1;
+                if state.senders > 0 {
+                    return;
+                }
+                state.wakers.take()
+            };
+            wake_all(wakers);
+        }
+    }
+
+    impl<T: Clone> Receiver<T> {
+        pub async fn recv(&mut self) -> Result<T, RecvError> {
+            poll_fn(|cx| self.poll_recv(cx)).await
+        }
+
+        pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, RecvError>> {
+            let mut state = self.state.lock().unwrap();
+            match Self::take(&state, &mut self.next) {
+                Err(TryRecvError::Empty) => {
+                    state.wakers.register(self.id, cx.waker());
+                    Poll::Pending
+                },
+                Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError::Closed)),
+                Err(TryRecvError::Lagged(skipped)) => Poll::Ready(Err(RecvError::Lagged(skipped))),
+                Ok(value) => Poll::Ready(Ok(value)),
+            }
+        }
+
+        pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+            let state = self.state.lock().unwrap();
+            Self::take(&state, &mut self.next)
+        }
+
+        fn take(state: &State<T>, next: &mut u64) -> Result<T, TryRecvError> {
+            let head = state.head_pos();
+            if *next < head {
+                let skipped = head - *next;
+                *next = head;
+                return Err(TryRecvError::Lagged(skipped));
+            }
+            if *next < state.next_pos {
+                let value = state.buffer[(*next - head) as usize].clone();
+                *next += 1;
+                return Ok(value);
+            }
+            if state.senders == 0 {
+                Err(TryRecvError::Closed)
+            } else {
+                Err(TryRecvError::Empty)
+            }
+        }
+    }
+
+    impl<T> Clone for Receiver<T> {
+        // 新接收端从同一位置开始读取
+        fn clone(&self) -> Self {
+            let mut state = self.state.lock().unwrap();
+            state.receivers += 1;
+            let id = state.wakers.next_id();
+            Receiver { state: self.state.clone(), id, next: self.next }
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            let mut state = self.state.lock().unwrap();
+            state.receivers -= 1;
+            state.wakers.remove(self.id);
+        }
+    }
+}
+
+// 只保留最新值的通道：接收端等待值发生变化，适合广播配置或状态
+pub mod watch {
+    use super::{wake_all, RecvError, SendError, Wakers};
+    use std::future::poll_fn;
+    use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+    use std::task::Poll;
+
+    struct State {
+        version: u64,
+        sender_dropped: bool,
+        receivers: usize,
+        wakers: Wakers,
+    }
+
+    struct Shared<T> {
+        value: RwLock<T>,
+        state: Mutex<State>,
+    }
+
+    pub struct Sender<T> {
+        shared: Arc<Shared<T>>,
+    }
+
+    pub struct Receiver<T> {
+        shared: Arc<Shared<T>>,
+        id: usize,
+        // 最近一次看到的版本
+        seen: u64,
+    }
+
+    pub fn channel<T>(init: T) -> (Sender<T>, Receiver<T>) {
+        let mut wakers = Wakers::default();
+        let id = wakers.next_id();
+        let shared = Arc::new(Shared {
+            value: RwLock::new(init),
+            state: Mutex::new(State {
+                version: 0,
+                sender_dropped: false,
+                receivers: 1,
+                wakers,
+            }),
+        });
+        (Sender { shared: shared.clone() }, Receiver { shared, id, seen: 0 })
+    }
+
+    impl<T> Sender<T> {
+        // 没有接收端时失败，值不会被替换
+        pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+            if self.shared.state.lock().unwrap().receivers == 0 {
+                return Err(SendError(value));
+            }
+            self.send_replace(value);
+            Ok(())
+        }
+
+        // 无论有没有接收端都替换，返回旧值
+        pub fn send_replace(&self, value: T) -> T {
+            let old = std::mem::replace(&mut *self.shared.value.write().unwrap(), value);
+            let wakers = {
+                let mut state = self.shared.state.lock().unwrap();
+                state.version += 1;
+                state.wakers.take()
+            };
+            wake_all(wakers);
+            old
+        }
+
+        // 返回读锁，持有期间发送方会被阻塞，不要跨 await 持有
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.shared.value.read().unwrap()
+        }
+
+        pub fn subscribe(&self) -> Receiver<T> {
+            let mut state = self.shared.state.lock().unwrap();
+            state.receivers += 1;
+            let id = state.wakers.next_id();
+            Receiver { shared: self.shared.clone(), id, seen: state.version }
+        }
+
+        pub fn receiver_count(&self) -> usize {
+            self.shared.state.lock().unwrap().receivers
+        }
+    }
+
+    impl<T> Drop for Sender<T> {
+        fn drop(&mut self) {
+            let wakers = {
+                let mut state = self.shared.state.lock().unwrap();
+                state.sender_dropped = true;
+                state.wakers.take()
+            };
+            wake_all(wakers);
+        }
+    }
+
+    impl<T> Receiver<T> {
+        // 读取当前值，不标记为已读
+        pub fn borrow(&self) -> RwLockReadGuard<'_, T> {
+            self.shared.value.read().unwrap()
+        }
+
+        // 读取当前值并标记为已读
+        pub fn borrow_and_update(&mut self) -> RwLockReadGuard<'_, T> {
+            // 先记录版本再读值：两者之间的更新会在下一次 changed 中再报告一次，不会漏掉
+            self.seen = self.shared.state.lock().unwrap().version;
+            self.shared.value.read().unwrap()
+        }
+
+        pub fn has_changed(&self) -> Result<bool, RecvError> {
+            let state = self.shared.state.lock().unwrap();
+            if state.version != self.seen {
+                Ok(true)
+            } else if state.sender_dropped {
+                Err(RecvError)
+            } else {
+                Ok(false)
+            }
+        }
+
+        // 等待出现未读的新值；发送端关闭后返回 RecvError
+        pub async fn changed(&mut self) -> Result<(), RecvError> {
+            poll_fn(|cx| {
+                let mut state = self.shared.state.lock().unwrap();
+                if state.version != self.seen {
+                    self.seen = state.version;
+                    return Poll::Ready(Ok(()));
+                }
+                if state.sender_dropped {
+                    return Poll::Ready(Err(RecvError));
+                }
+                state.wakers.register(self.id, cx.waker());
+                Poll::Pending
+            })
+            .await
+        }
+    }
+
+    impl<T> Clone for Receiver<T> {
+        fn clone(&self) -> Self {
+            let mut state = self.shared.state.lock().unwrap();
+            state.receivers += 1;
+            let id = state.wakers.next_id();
+            Receiver { shared: self.shared.clone(), id, seen: self.seen }
+        }
+    }
+
+    impl<T> Drop for Receiver<T> {
+        fn drop(&mut self) {
+            let mut state = self.shared.state.lock().unwrap();
+            state.receivers -= 1;
+            state.wakers.remove(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{self, Simulation};
+    use futures_lite::future::{block_on, poll_once};
+    use std::pin::pin;
+
+    #[test]
+    fn oneshot_delivers_value_or_reports_drop() {
+        let (tx, rx) = oneshot::channel();
+        tx.send(5).unwrap();
+        assert_eq!(block_on(rx), Ok(5));
+
+        let (tx, rx) = oneshot::channel::<i32>();
+        drop(tx);
+        assert_eq!(block_on(rx), Err(RecvError));
+
+        let (tx, mut rx) = oneshot::channel();
+        rx.close();
+        assert!(tx.is_closed());
+        assert_eq!(tx.send(1), Err(1));
+    }
+
+    #[test]
+    fn bounded_mpsc_applies_backpressure() {
+        let (tx, mut rx) = mpsc::channel(1);
+        tx.try_send(1).unwrap();
+        assert!(matches!(tx.try_send(2), Err(mpsc::TrySendError::Full(2))));
+
+        let mut send = pin!(tx.send(2));
+        assert!(block_on(poll_once(send.as_mut())).is_none());
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert!(block_on(poll_once(send.as_mut())).unwrap().is_ok());
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn mpsc_close_releases_waiting_senders() {
+        let (tx, mut rx) = mpsc::channel(1);
+        tx.try_send(1).unwrap();
+        let mut send = pin!(tx.send(2));
+        assert!(block_on(poll_once(send.as_mut())).is_none());
+
+        rx.close();
+        assert_eq!(block_on(poll_once(send.as_mut())).unwrap().map_err(|SendError(value)| value), Err(2));
+        // 关闭前的数据仍然可以取出
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn mpsc_recv_ends_when_senders_drop() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let received = Simulation::new(5).block_on(async move {
+            for id in 0..3 {
+                let tx = tx.clone();
+                sim::spawn(async move { tx.send(id).unwrap() }).detach();
+            }
+            drop(tx);
+            let mut received = Vec::new();
+            while let Some(value) = rx.recv().await {
+                received.push(value);
+            }
+            received.sort();
+            received
+        });
+        assert_eq!(received, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn broadcast_reaches_every_receiver_and_reports_lag() {
+        let (tx, mut first) = broadcast::channel(2);
+        let mut second = tx.subscribe();
+        assert_eq!(tx.send(1).unwrap(), 2);
+        assert_eq!(first.try_recv(), Ok(1));
+        assert_eq!(second.try_recv(), Ok(1));
+
+        for value in 2..5 {
+            tx.send(value).unwrap();
+        }
+        assert_eq!(first.try_recv(), Err(broadcast::TryRecvError::Lagged(1)));
+        assert_eq!(first.try_recv(), Ok(3));
+        assert_eq!(first.try_recv(), Ok(4));
+
+        drop(tx);
+        assert_eq!(block_on(first.recv()), Err(broadcast::RecvError::Closed));
+        assert_eq!(block_on(second.recv()), Err(broadcast::RecvError::Lagged(1)));
+    }
+
+    #[test]
+    fn watch_reports_only_latest_value() {
+        let (tx, mut rx) = watch::channel(0);
+        assert_eq!(rx.has_changed(), Ok(false));
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.has_changed(), Ok(true));
+        assert_eq!(block_on(rx.changed()), Ok(()));
+        assert_eq!(*rx.borrow(), 2);
+
+        let mut changed = pin!(rx.changed());
+        assert!(block_on(poll_once(changed.as_mut())).is_none());
+        drop(tx);
+        assert_eq!(block_on(poll_once(changed.as_mut())), Some(Err(RecvError)));
+    }
+}
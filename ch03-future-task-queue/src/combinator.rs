@@ -0,0 +1,336 @@
+//! future 组合
+//! join! / try_join! / select! 以及 join_all / select_all 在同一个任务中并发 poll 所有分支：
+//! 任一分支被唤醒时整个组合被重新 poll，各分支的等待时间互相重叠，而不是逐个 block_on；
+//! 宏展开中包含 .await，只能在 async 上下文中使用
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+// 保存分支的 future 或其输出，完成后不再 poll
+#[doc(hidden)]
+pub enum MaybeDone<F: Future> {
+    Future(Pin<Box<F>>),
+    Done(F::Output),
+    Gone,
+}
+
+impl<F: Future> MaybeDone<F> {
+    pub fn new(future: F) -> Self {
+        MaybeDone::Future(Box::pin(future))
+    }
+
+    // 返回分支是否已经完成
+    pub fn poll(&mut self, cx: &mut Context<'_>) -> bool {
+        match self {
+            MaybeDone::Future(future) => match future.as_mut().poll(cx) {
+                Poll::Ready(output) => {
+                    *self = MaybeDone::Done(output);
+                    true
+                },
+                Poll::Pending => false,
+            },
+            MaybeDone::Done(_) => true,
+            MaybeDone::Gone => panic!("MaybeDone polled after its output was taken"),
+        }
+    }
+
+    pub fn take_output(&mut self) -> F::Output {
+        match std::mem::replace(self, MaybeDone::Gone) {
+            MaybeDone::Done(output) => output,
+            _ => panic!("MaybeDone has no output"),
+        }
+    }
+}
+
+impl<F, T, E> MaybeDone<F>
+    where F: Future<Output = Result<T, E>>
+{
+    // 分支以 Err 完成时取出错误
+    pub fn take_err(&mut self) -> Option<E> {
+        match self {
+            MaybeDone::Done(Err(_)) => match self.take_output() {
+                Err(error) => Some(error),
+                Ok(_) => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+
+    pub fn take_ok(&mut self) -> T {
+        match self.take_output() {
+            Ok(output) => output,
+            Err(_) => panic!("MaybeDone holds an error"),
+        }
+    }
+}
+
+// 等待所有 future 完成，按传入顺序返回输出：
+// let (a, b) = join!(fetch(1), fetch(2));
+#[macro_export]
+macro_rules! join {
+    // 为每个分支生成解构元组用的 `_` 前缀：第 n 个分支前面有 n 个 `_`
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $future:expr, )* } $head:expr, $($tail:expr,)*) => {
+        $crate::join!(@ { ( $($count)* _ ) $( ( $($skip)* ) $future, )* ( $($count)* ) $head, } $($tail,)*)
+    };
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $future:expr, )* }) => {{
+        let mut futures = ( $( $crate::combinator::MaybeDone::new($future), )* );
+        std::future::poll_fn(|cx| {
+            let mut done = true;
+            $(
+                let ( $($skip,)* future, .. ) = &mut futures;
+                done &= future.poll(cx);
+            )*
+            if !done {
+                return std::task::Poll::Pending;
+            }
+            std::task::Poll::Ready(( $({
+                let ( $($skip,)* future, .. ) = &mut futures;
+                future.take_output()
+            },)* ))
+        })
+        .await
+    }};
+    ($($future:expr),+ $(,)?) => {
+        $crate::join!(@ { () } $($future,)+)
+    };
+}
+
+// 每个 future 输出 Result<T, E>：全部成功时返回 Ok((..))，第一个 Err 立即返回并丢弃其余分支
+#[macro_export]
+macro_rules! try_join {
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $future:expr, )* } $head:expr, $($tail:expr,)*) => {
+        $crate::try_join!(@ { ( $($count)* _ ) $( ( $($skip)* ) $future, )* ( $($count)* ) $head, } $($tail,)*)
+    };
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $future:expr, )* }) => {{
+        let mut futures = ( $( $crate::combinator::MaybeDone::new($future), )* );
+        std::future::poll_fn(|cx| {
+            let mut done = true;
+            $(
+                let ( $($skip,)* future, .. ) = &mut futures;
+                if future.poll(cx) {
+                    if let Some(error) = future.take_err() {
+                        return std::task::Poll::Ready(Err(error));
+                    }
+                } else {
+                    done = false;
+                }
+            )*
+            if !done {
+                return std::task::Poll::Pending;
+            }
+            std::task::Poll::Ready(Ok(( $({
+                let ( $($skip,)* future, .. ) = &mut futures;
+                future.take_ok()
+            },)* )))
+        })
+        .await
+    }};
+    ($($future:expr),+ $(,)?) => {
+        $crate::try_join!(@ { () } $($future,)+)
+    };
+}
+
+// 等待第一个完成的分支，执行对应的处理表达式，其余分支在执行前被丢弃：
+// select! {
+//     line = receiver.recv() => println!("{:?}", line),
+//     _ = sleep(Duration::from_secs(1)) => println!("timeout"),
+// }
+// 按书写顺序 poll，同时就绪时靠前的分支优先；模式必须是不可反驳的
+#[macro_export]
+macro_rules! select {
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $pat:pat = $future:expr => $handler:expr, )* } $hpat:pat = $head:expr => $hhandler:expr, $($tail:tt)*) => {
+        $crate::select!(@ { ( $($count)* _ ) $( ( $($skip)* ) $pat = $future => $handler, )* ( $($count)* ) $hpat = $head => $hhandler, } $($tail)*)
+    };
+    (@ { ( $($count:tt)* ) $( ( $($skip:tt)* ) $pat:pat = $future:expr => $handler:expr, )* }) => {{
+        let mut futures = ( $( $crate::combinator::MaybeDone::new($future), )* );
+        let selected: usize = std::future::poll_fn(|cx| {
+            $(
+                let ( $($skip,)* future, .. ) = &mut futures;
+                if future.poll(cx) {
+                    return std::task::Poll::Ready(0 $(+ $crate::__select_one!($skip))*);
+                }
+            )*
+            std::task::Poll::Pending
+        })
+        .await;
+        $(
+            if selected == 0 $(+ $crate::__select_one!($skip))* {
+                let ( $($skip,)* future, .. ) = &mut futures;
+                let output = future.take_output();
+                drop(futures);
+                let $pat = output;
+                // 处理表达式常写成代码块，展开后不应触发 unused_braces
+                #[allow(unused_braces)]
+                let value = $handler;
+                value
+            } else
+        )* {
+            unreachable!()
+        }
+    }};
+    ($($pat:pat = $future:expr => $handler:expr),+ $(,)?) => {
+        $crate::select!(@ { () } $($pat = $future => $handler,)+)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __select_one {
+    (_) => { 1 };
+}
+
+// join_all 返回的 future
+pub struct JoinAll<F: Future> {
+    futures: Vec<MaybeDone<F>>,
+}
+
+// 并发等待一组同类型的 future，按传入顺序返回输出
+pub fn join_all<I>(futures: I) -> JoinAll<I::Item>
+    where I: IntoIterator,
+    I::Item: Future
+{
+    JoinAll { futures: futures.into_iter().map(MaybeDone::new).collect() }
+}
+
+impl<F: Future> Future for JoinAll<F> {
+    type Output = Vec<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut done = true;
+        for future in self.futures.iter_mut() {
+            done &= future.poll(cx);
+        }
+        if !done {
+            return Poll::Pending;
+        }
+        Poll::Ready(self.futures.iter_mut().map(MaybeDone::take_output).collect())
+    }
+}
+
+// future 已经装箱，输出只会被移出，不依赖固定地址
+impl<F: Future> Unpin for JoinAll<F> {}
+
+impl<F: Future> fmt::Debug for JoinAll<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinAll").field("len", &self.futures.len()).finish()
+    }
+}
+
+// select_all 返回的 future
+pub struct SelectAll<F: Future> {
+    futures: Vec<Pin<Box<F>>>,
+}
+
+// 等待一组 future 中第一个完成的，返回其输出、下标以及其余尚未完成的 future；传入空集合时 panic
+pub fn select_all<I>(futures: I) -> SelectAll<I::Item>
+    where I: IntoIterator,
+    I::Item: Future
+{
+    let futures: Vec<_> = futures.into_iter().map(Box::pin).collect();
+    assert!(!futures.is_empty(), "select_all requires at least one future");
+    SelectAll { futures }
+}
+
+impl<F: Future> Future for SelectAll<F> {
+    type Output = (F::Output, usize, Vec<Pin<Box<F>>>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // 构造时保证非空，完成后其余的 future 已经随输出移出
+        assert!(!self.futures.is_empty(), "SelectAll polled after completion");
+        let ready = self.futures
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, future)| match future.as_mut().poll(cx) {
+                Poll::Ready(output) => Some((index, output)),
+                Poll::Pending => None,
+            });
+        match ready {
+            Some((index, output)) => {
+                let mut rest = std::mem::take(&mut self.futures);
+                drop(rest.remove(index));
+                Poll::Ready((output, index, rest))
+            },
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<F: Future> fmt::Debug for SelectAll<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectAll").field("len", &self.futures.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Simulation;
+    use crate::timer;
+    use futures_lite::future::{block_on, poll_once};
+    use std::time::Duration;
+
+    async fn after<T>(millis: u64, value: T) -> T {
+        timer::sleep(Duration::from_millis(millis)).await;
+        value
+    }
+
+    #[test]
+    fn join_waits_overlap() {
+        let simulation = Simulation::new(1);
+        let output = simulation.block_on(async { join!(after(30, 1), after(10, "b"), after(20, 3.0)) });
+        assert_eq!(output, (1, "b", 3.0));
+        assert_eq!(simulation.elapsed(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn try_join_returns_first_error() {
+        let simulation = Simulation::new(1);
+        let output: Result<(i32, i32), &str> = simulation.block_on(async {
+            try_join!(after(100, Ok(1)), after(10, Err("failed")))
+        });
+        assert_eq!(output, Err("failed"));
+        assert_eq!(simulation.elapsed(), Duration::from_millis(10));
+
+        let output: Result<(i32, i32), &str> = simulation.block_on(async { try_join!(after(10, Ok(1)), after(20, Ok(2))) });
+        assert_eq!(output, Ok((1, 2)));
+    }
+
+    #[test]
+    fn select_runs_first_ready_branch() {
+        let simulation = Simulation::new(1);
+        let output = simulation.block_on(async {
+            select! {
+                value = after(50, 1) => value * 10,
+                value = after(20, 2) => value * 100,
+            }
+        });
+        assert_eq!(output, 200);
+        assert_eq!(simulation.elapsed(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn join_all_keeps_input_order() {
+        let simulation = Simulation::new(1);
+        let output = simulation.block_on(join_all([30, 10, 20].map(|millis| after(millis, millis))));
+        assert_eq!(output, vec![30, 10, 20]);
+        assert_eq!(simulation.elapsed(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn select_all_returns_index_and_rest() {
+        let simulation = Simulation::new(1);
+        let (output, index, rest) = simulation.block_on(select_all([30, 10, 20].map(|millis| after(millis, millis))));
+        assert_eq!((output, index, rest.len()), (10, 1, 2));
+        assert_eq!(simulation.block_on(join_all(rest)), vec![30, 20]);
+    }
+
+    #[test]
+    #[should_panic(expected = "SelectAll polled after completion")]
+    fn select_all_panics_when_polled_after_completion() {
+        let mut select = select_all([std::future::ready(1)]);
+        assert!(block_on(poll_once(&mut select)).is_some());
+        let _ = block_on(poll_once(&mut select));
+    }
+}
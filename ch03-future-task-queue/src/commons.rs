@@ -1,81 +1,107 @@
-use log::{error, info};
 use std::pin::Pin;
-use std::task::{Context, Poll};
-use std::time::{Duration, Instant};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
 use std::future::Future;
-use std::sync::LazyLock;
-use async_task::{Runnable, Task};
-use flume::{Sender, Receiver};
-
-pub static HIGH_CHANNEL: LazyLock<(Sender<Runnable>, Receiver<Runnable>)> = LazyLock::new(|| flume::unbounded::<Runnable>());
-pub static LOW_CHANNEL: LazyLock<(Sender<Runnable>, Receiver<Runnable>)> = LazyLock::new(|| flume::unbounded::<Runnable>());
+use std::net::SocketAddr;
+use crate::blocking;
+use crate::multi_worker_queue::Handle;
+use crate::timer::{self, Sleep};
 
 pub async fn async_fn() {
-    std::thread::sleep(Duration::from_secs(1));
+    // 阻塞调用交给 spawn_blocking 线程池，不占住 worker 线程
+    let _ = blocking::spawn_blocking(|| std::thread::sleep(Duration::from_secs(1))).await;
     println!("async fn");
 }
 
+// 基于定时器驱动：到期前只登记一次 waker，不再每次 poll 都 wake_by_ref
 pub struct AsyncSleep {
-    start_time: Instant,
-    duration: Duration
+    sleep: Sleep,
 }
 
 impl AsyncSleep {
-    fn new(duration: Duration) -> Self {
+    pub fn new(duration: Duration) -> Self {
         AsyncSleep {
-            start_time: Instant::now(),
-            duration
+            sleep: timer::sleep(duration),
         }
     }
 }
 
 impl Future for AsyncSleep {
     type Output = bool;
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self.start_time.elapsed() >= self.duration {
-            Poll::Ready(true)
-        } else {
-            cx.waker().wake_by_ref();
-            Poll::Pending
-        }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.sleep).poll(cx).map(|_| true)
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+// High 对应最高级（0），Low 对应最低级（priority_levels - 1），Level(n) 指定中间级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FutureType {
-    High, Low
+    High, Low,
+    Level(usize),
 }
 
-
-macro_rules! join_future {
-    ($($future:expr), *) => {
-        {
-            let mut result = Vec::new();
-            $(
-                result.push(futures_lite::future::block_on($future));
-            )*
-            result
+impl FutureType {
+    // 换算成队列下标，超出配置级数的 Level 按最低级处理
+    pub(crate) fn level(self, levels: usize) -> usize {
+        let lowest = levels.max(1) - 1;
+        match self {
+            FutureType::High => 0,
+            FutureType::Low => lowest,
+            FutureType::Level(level) => level.min(lowest),
         }
-    };
+    }
 }
 
-// 直接运行Task，返回结果集合
-macro_rules! try_join {
-    ($($future:expr), *) => {
-        {
-            let mut result = Vec::new();
-            $(
-                let res = std::panic::catch_unwind(|| future::block_on($future));
-                result.push(res);
-            )*
-            result
-        }
+
+// 由运行时（Runtime 或 Handle）的 block_on 并发等待一组输出类型相同的 future，按传入顺序返回结果集合：
+// join_future!(runtime; task1, task2)
+#[macro_export]
+macro_rules! join_future {
+    ($runtime:expr; $($future:expr), *) => {
+        $runtime.block_on($crate::combinator::join_all(vec![
+            $(Box::pin($future) as std::pin::Pin<Box<dyn std::future::Future<Output = _>>>), *
+        ]))
     };
 }
 
 pub struct Runtime {
     pub high_num: usize,
     pub low_num: usize,
+    // 是否使用工作窃取调度（每个 worker 一个本地队列）
+    pub work_stealing: bool,
+    // HIGH / LOW worker 绑定的 CPU 编号，第 n 个 worker 绑定 cores[n % len]；为空时不绑定（仅 Linux）
+    pub high_cores: Vec<usize>,
+    pub low_cores: Vec<usize>,
+    // thread-per-core：每个 CPU 一个绑定的 worker 和独立队列，任务不在核心之间迁移；
+    // 空列表表示使用所有 CPU，开启后忽略 high_num / low_num 与 work_stealing
+    pub thread_per_core: Option<Vec<usize>>,
+    // spawn_blocking 线程池的线程数上限
+    pub max_blocking_threads: usize,
+    // 优先级级数，至少为 1；默认 2 级即 High / Low
+    pub priority_levels: usize,
+    // 老化周期：任务每排队等待一个周期，有效优先级提升一级，避免低优先级任务饿死
+    // 工作窃取模式无法查看排队时间，改为周期性地从最低级开始取任务
+    pub aging: Duration,
+    // 单次 poll 超过该时长时打印警告，默认 None 不检测
+    pub slow_poll_threshold: Option<Duration>,
+    // 是否汇总慢 poll 任务报告，见 Runtime::slow_tasks()
+    pub slow_poll_report: bool,
+    // Runtime::block_on 等待时调用线程是否帮忙执行排队的任务
+    pub block_on_help: bool,
+    // 排队任务数上限，None 为无界；满时 try_spawn 返回 SpawnError::Full，spawn_async 等待空位
+    pub queue_capacity: Option<usize>,
+    // 在该地址上以文本形式提供 Runtime::dump()，见 Handle::serve_dump
+    pub dump_addr: Option<SocketAddr>,
+    // 记录任务调度事件，见 Handle::write_trace
+    pub trace: bool,
+    // run() 之后持有的运行时句柄
+    pub(crate) handle: Option<Handle>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Runtime {
@@ -83,8 +109,22 @@ impl Runtime {
         let core_num = std::thread::available_parallelism().unwrap().get();
 
         Self {
-            high_num: core_num - 2,
-            low_num: 1
+            high_num: core_num.saturating_sub(2).max(1),
+            low_num: 1,
+            work_stealing: false,
+            high_cores: Vec::new(),
+            low_cores: Vec::new(),
+            thread_per_core: None,
+            max_blocking_threads: 512,
+            priority_levels: 2,
+            aging: Duration::from_millis(100),
+            slow_poll_threshold: None,
+            slow_poll_report: false,
+            block_on_help: false,
+            queue_capacity: None,
+            dump_addr: None,
+            trace: false,
+            handle: None,
         }
     }
 
@@ -97,17 +137,101 @@ impl Runtime {
         self.low_num = num;
         self
     }
+
+    pub fn with_work_stealing(mut self, enabled: bool) -> Self {
+        self.work_stealing = enabled;
+        self
+    }
+
+    pub fn with_high_cores(mut self, cores: Vec<usize>) -> Self {
+        self.high_cores = cores;
+        self
+    }
+
+    pub fn with_low_cores(mut self, cores: Vec<usize>) -> Self {
+        self.low_cores = cores;
+        self
+    }
+
+    // 例如 with_thread_per_core(vec![2, 3]) 只使用 CPU 2 和 3，vec![] 使用所有 CPU
+    pub fn with_thread_per_core(mut self, cores: Vec<usize>) -> Self {
+        self.thread_per_core = Some(cores);
+        self
+    }
+
+    pub fn with_max_blocking_threads(mut self, num: usize) -> Self {
+        self.max_blocking_threads = num;
+        self
+    }
+
+    pub fn with_priority_levels(mut self, levels: usize) -> Self {
+        self.priority_levels = levels.max(1);
+        self
+    }
+
+    // Duration::ZERO 关闭老化，严格按优先级调度
+    pub fn with_aging(mut self, aging: Duration) -> Self {
+        self.aging = aging;
+        self
+    }
+
+    // 排查阻塞 worker 的任务时开启，例如 Some(Duration::from_millis(100))；
+    // 汇总报告（with_slow_poll_report）同样需要设置阈值
+    pub fn with_slow_poll_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_poll_threshold = threshold;
+        self
+    }
+
+    pub fn with_slow_poll_report(mut self, enabled: bool) -> Self {
+        self.slow_poll_report = enabled;
+        self
+    }
+
+    pub fn with_block_on_help(mut self, enabled: bool) -> Self {
+        self.block_on_help = enabled;
+        self
+    }
+
+    // 容量只约束 try_spawn / try_spawn_with（满时返回 SpawnError::Full）和 spawn_async（等待空位）；
+    // spawn / spawn_with / spawn_labeled、spawn_task_macro! 与 Builder 不做检查，总是入队，可能超过容量。
+    // 同步的 spawn 无法在 worker 上等待空位，需要背压的生产者应改用 try_spawn 或 spawn_async
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    // 只应绑定本机地址，例如 127.0.0.1:6669
+    pub fn with_dump_addr(mut self, addr: SocketAddr) -> Self {
+        self.dump_addr = Some(addr);
+        self
+    }
+
+    // 每个事件都要加锁记录，只在排查调度问题时开启
+    pub fn with_trace(mut self, enabled: bool) -> Self {
+        self.trace = enabled;
+        self
+    }
 }
 
-// 后台 Future
-#[derive(Debug, Clone, Copy)]
-pub struct BackgroundProcess;
+// 后台 Future：每秒醒来一次，永不完成；等待交给定时器，不占住 worker 线程
+#[derive(Default)]
+pub struct BackgroundProcess {
+    sleep: Option<Sleep>,
+}
+
+impl BackgroundProcess {
+    pub fn new() -> Self {
+        BackgroundProcess { sleep: None }
+    }
+}
 
 impl Future for BackgroundProcess {
     type Output = ();
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        std::thread::sleep(Duration::from_secs(1));
-        cx.waker().wake_by_ref();
-        Poll::Pending
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let sleep = self.sleep.get_or_insert_with(|| timer::sleep(Duration::from_secs(1)));
+            ready!(Pin::new(sleep).poll(cx));
+            self.sleep = None;
+        }
     }
 }
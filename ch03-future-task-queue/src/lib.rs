@@ -5,10 +5,30 @@ use commons::{async_fn, FutureType, Runtime, BackgroundProcess};
 mod single_worker_queue;
 use single_worker_queue::CounterFuture as SingleCounterFuture;
 use futures_lite::future;
+use multi_worker_queue::FutureOrderExt;
 use std::time::Duration;
 
 #[macro_use]
 pub mod multi_worker_queue;
+pub mod task;
+#[macro_use]
+pub mod task_local;
+pub mod timer;
+pub mod reactor;
+pub mod blocking;
+pub mod cancel;
+pub mod metrics;
+pub(crate) mod trace;
+pub mod local_executor;
+pub mod sim;
+pub mod scope;
+pub mod sync;
+pub mod channel;
+#[macro_use]
+pub mod combinator;
+mod work_stealing_queue;
+mod per_core_queue;
+mod affinity;
 
 pub fn sing_task() {
     let one = SingleCounterFuture { count: 0};
@@ -33,39 +53,234 @@ pub fn sing_task() {
 }
 
 pub fn multi_task() {
-    let high_counter = multi_worker_queue::CounterFuture { count: 0};
-    let low_counter = multi_worker_queue::CounterFuture { count: 0};
+    // 记录调度事件，结束时写出 trace 文件
+    let mut runtime = Runtime::new().with_trace(true);
+    let handle = runtime.run();
+
+    let high_counter = multi_worker_queue::CounterFuture::new();
+    let low_counter = multi_worker_queue::CounterFuture::new();
 
-    let task1 = spawn_task_macro!(high_counter, FutureType::High);
-    let task2 = spawn_task_macro!(low_counter);
+    // 优先级随 future 一起传递
+    let task1 = spawn_task_macro!(handle, labeled high_counter.with_priority(FutureType::High));
+    let task2 = spawn_task_macro!(handle, low_counter);
 
-    let task3 = spawn_task_macro!(async_fn());
+    let task3 = spawn_task_macro!(handle, async_fn());
 
-    let task4 = spawn_task_macro!(async {
+    let task4 = spawn_task_macro!(handle, async {
         async_fn().await;
         async_fn().await;
         async_fn().await;
         async_fn().await;
     }, FutureType::High);
 
-    std::thread::sleep(Duration::from_secs(5));
-    println!("before block on");
+    // runtime.block_on(task1);
+    // runtime.block_on(task2);
+    // runtime.block_on(task3);
+    // runtime.block_on(task4);
 
-    // future::block_on(task1);
-    // future::block_on(task2);
-    // future::block_on(task3);
-    // future::block_on(task4);
+    // let outcome: Vec<Result<u32, JoinError>> = join_future!(runtime; task1, task2);
+    // let outcome_next: Vec<Result<(), JoinError>> = join_future!(runtime; task3, task4);
 
-    // let outcome: Vec<u32> = join_future!(task1, task2);
-    // let outcome_next: Vec<()> = join_future!(task3, task4);
+    // 四个任务并发等待，遇到第一个 panic 或取消的任务立即返回其 JoinError
+    let _cout = runtime.block_on(async { try_join!(task1, task2, task3, task4) });
 
-    let cout = try_join!(task1, task2);
-    let cout = try_join!(task3, task4);
+    // 在 chrome://tracing 或 https://ui.perfetto.dev 中打开
+    match handle.write_trace("multi_task.trace.json") {
+        Ok(()) => println!("trace written to multi_task.trace.json"),
+        Err(err) => println!("failed to write trace: {}", err),
+    }
+    runtime.shutdown(Duration::from_secs(1));
 }
 
 pub fn multi_task_runtime() {
-    Runtime::new().run();
-    // Runtime::new().with_high_num(4).with_low_num(1).run();
+    // Runtime 被丢弃时关闭运行时，需要一直持有到任务结束
+    let mut runtime = Runtime::new();
+    // let mut runtime = Runtime::new().with_high_num(4).with_low_num(1);
+    // HIGH worker 绑定到 CPU 0、1，LOW worker 绑定到 CPU 2（仅 Linux）
+    // let mut runtime = Runtime::new().with_high_cores(vec![0, 1]).with_low_cores(vec![2]);
+    // 卡住时用 nc 127.0.0.1 6669 查看存活任务
+    // let mut runtime = Runtime::new().with_dump_addr(([127, 0, 0, 1], 6669).into());
+    let handle = runtime.run();
     // detach: 让 Task 在后台运行
-    spawn_task_macro!(BackgroundProcess{}).detach();
-}
\ No newline at end of file
+    spawn_task_macro!(handle, named "background", BackgroundProcess::new()).detach();
+
+    // 后台任务运行几秒后关闭运行时，它会出现在未完成任务的报告中
+    runtime.block_on(timer::sleep(Duration::from_secs(3)));
+    let report = runtime.shutdown(Duration::from_millis(100));
+    println!("shutdown report: {:?}", report);
+}
+
+pub fn stealing_task() {
+    let mut runtime = Runtime::new().with_work_stealing(true);
+    let handle = runtime.run();
+
+    let tasks: Vec<_> = (0..4)
+        .map(|i| {
+            let order = if i % 2 == 0 { FutureType::High } else { FutureType::Low };
+            handle.spawn_with(multi_worker_queue::CounterFuture::new(), order)
+        })
+        .collect();
+
+    // 由运行时自己的 block_on 在当前线程等待，不再混用其他执行器
+    let outcome: Vec<u32> = runtime.block_on(async {
+        let mut outcome = Vec::new();
+        for task in tasks {
+            outcome.push(task.await.unwrap());
+        }
+        outcome
+    });
+    println!("outcome: {:?}", outcome);
+}
+
+pub fn per_core_task() {
+    // 每个 CPU 一个 worker，任务及其在 worker 上 spawn 的子任务始终留在同一个核心
+    let mut runtime = Runtime::new().with_thread_per_core(vec![]);
+    let handle = runtime.run();
+
+    let tasks: Vec<_> = (0..4)
+        .map(|i| {
+            spawn_task_macro!(handle, async move {
+                let core = std::thread::current().name().unwrap_or_default().to_string();
+                let child = multi_worker_queue::Handle::current().spawn(async {
+                    std::thread::current().name().unwrap_or_default().to_string()
+                });
+                let child_core = child.await.unwrap();
+                println!("task {} on {}, child on {}", i, core, child_core);
+            })
+        })
+        .collect();
+
+    runtime.block_on(async {
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+    runtime.shutdown(Duration::from_secs(1));
+}
+
+pub fn local_task() {
+    let executor = local_executor::LocalExecutor::new();
+    // Rc 不是 Send，只能交给单线程执行器
+    let counter = std::rc::Rc::new(std::cell::RefCell::new(0));
+
+    let outcome = executor.block_on(async {
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let counter = counter.clone();
+                local_executor::spawn_local(async move {
+                    timer::sleep(Duration::from_millis(100)).await;
+                    *counter.borrow_mut() += 1;
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        *counter.borrow()
+    });
+    println!("outcome: {}", outcome);
+}
+
+pub fn sim_task() {
+    let simulation = sim::Simulation::new(42);
+    // 虚拟时钟：一小时的 sleep 立即完成，同一个种子每次得到相同的完成顺序
+    let order = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+
+    simulation.block_on(async {
+        let tasks: Vec<_> = (0..3)
+            .map(|id| {
+                let order = order.clone();
+                sim::spawn(async move {
+                    timer::sleep(Duration::from_secs(3600)).await;
+                    future::yield_now().await;
+                    order.borrow_mut().push(id);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+    });
+    println!("seed: {}, elapsed: {:?}, order: {:?}", simulation.seed(), simulation.elapsed(), order.borrow());
+}
+
+pub fn scope_task() {
+    let mut runtime = Runtime::new();
+    runtime.run();
+    let words = vec!["async", "rust", "runtime"];
+
+    // 子任务直接借用 words，作用域在所有子任务结束后才返回，不需要 sleep 等待
+    let outcome: Result<usize, String> = runtime.block_on(async {
+        let words = &words;
+        scope::scope(|s| async move {
+            let tasks: Vec<_> = words
+                .iter()
+                .map(|word| s.spawn(async move {
+                    timer::sleep(Duration::from_millis(100)).await;
+                    Ok(word.len())
+                }))
+                .collect();
+            let mut total = 0;
+            for task in tasks {
+                total += task.await;
+            }
+            Ok(total)
+        })
+        .await
+    });
+    println!("outcome: {:?}", outcome);
+}
+
+pub fn sync_task() {
+    let mut runtime = Runtime::new();
+    let handle = runtime.run();
+    // 异步 Mutex：拿不到锁时登记 waker 排队，不再 try_lock 失败后 wake_by_ref 空转
+    let counter = std::sync::Arc::new(sync::Mutex::new(0));
+
+    let tasks: Vec<_> = (0..4)
+        .map(|i| {
+            let counter = counter.clone();
+            handle.spawn(async move {
+                let mut guard = counter.lock().await;
+                // guard 可以跨 await 持有
+                timer::sleep(Duration::from_millis(50)).await;
+                if i % 2 == 0 { *guard += 1 } else { *guard -= 1 }
+            })
+        })
+        .collect();
+
+    let outcome = runtime.block_on(async {
+        for task in tasks {
+            task.await.unwrap();
+        }
+        *counter.lock().await
+    });
+    println!("outcome: {}", outcome);
+}
+
+pub fn channel_task() {
+    let mut runtime = Runtime::new();
+    let handle = runtime.run();
+    // 有界通道：缓冲区满时生产者等待，不需要 tokio::sync::mpsc
+    let (sender, mut receiver) = channel::mpsc::channel(4);
+
+    for id in 0..3 {
+        let sender = sender.clone();
+        handle.spawn(async move {
+            for i in 0..5 {
+                sender.send(id * 10 + i).await.unwrap();
+            }
+        })
+        .detach();
+    }
+    drop(sender);
+
+    let outcome = runtime.block_on(async {
+        let mut outcome = Vec::new();
+        while let Some(value) = receiver.recv().await {
+            outcome.push(value);
+        }
+        outcome
+    });
+    println!("outcome: {:?}", outcome);
+}
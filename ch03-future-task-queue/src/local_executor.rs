@@ -0,0 +1,305 @@
+//! 单线程执行器
+//! 沿用 single_worker_queue 的单队列设计，但队列由调用 block_on 的线程自己消费，
+//! 因此可以运行持有 Rc、RefCell 等 !Send 数据的 future
+
+use crate::multi_worker_queue::ParkSignal;
+use crate::task::{spawn_local_task, JoinHandle, TaskRegistry};
+use async_task::Runnable;
+use flume::{Receiver, Sender};
+use std::cell::RefCell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::panic::{catch_unwind, Location};
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+struct Shared {
+    sender: Sender<Runnable>,
+    // 接收端也放在这里，执行器 drop 之后迟到的唤醒不会因为发送失败而在其他线程上丢弃 Runnable
+    receiver: Receiver<Runnable>,
+    registry: Arc<TaskRegistry>,
+    // 创建执行器的线程，任务只能在这个线程上运行和析构
+    thread: Thread,
+    // 执行器已被 drop，之后调度的任务不再执行
+    closed: AtomicBool,
+}
+
+impl Shared {
+    // waker 可能在其他线程（定时器、reactor）上调用：入队后唤醒执行器线程
+    fn schedule(&self, runnable: Runnable) {
+        // 关闭后在本线程上直接丢弃；其他线程上的唤醒照常入队，由 drop 中的排空循环在本线程丢弃
+        if self.closed.load(Ordering::Acquire) && thread::current().id() == self.thread.id() {
+            drop(runnable);
+            return;
+        }
+        self.sender.send(runnable).unwrap();
+        self.thread.unpark();
+    }
+}
+
+thread_local! {
+    // 正在 block_on 的执行器，供 spawn_local 使用
+    static CURRENT: RefCell<Option<Arc<Shared>>> = const { RefCell::new(None) };
+}
+
+// block_on 期间把执行器设置为当前执行器，结束时恢复（支持嵌套）
+struct Enter {
+    previous: Option<Arc<Shared>>,
+}
+
+impl Enter {
+    fn new(shared: &Arc<Shared>) -> Self {
+        let previous = CURRENT.with(|current| current.borrow_mut().replace(shared.clone()));
+        Enter { previous }
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+// 单线程执行器：自身不是 Send，只能在创建它的线程上使用
+pub struct LocalExecutor {
+    shared: Arc<Shared>,
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl Default for LocalExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalExecutor {
+    pub fn new() -> Self {
+        let (sender, receiver) = flume::unbounded();
+        LocalExecutor {
+            shared: Arc::new(Shared {
+                sender,
+                receiver,
+                registry: Arc::new(TaskRegistry::new()),
+                thread: thread::current(),
+                closed: AtomicBool::new(false),
+            }),
+            _not_send: PhantomData,
+        }
+    }
+
+    // 提交 !Send 的 future，任务在 block_on 中执行
+    #[track_caller]
+    pub fn spawn_local<F, T>(&self, future: F) -> JoinHandle<T>
+        where F: Future<Output = T> + 'static,
+        T: 'static
+    {
+        spawn_on(&self.shared, Location::caller(), future)
+    }
+
+    // 在当前线程上运行 future 直到完成，期间执行所有本地任务；没有可执行的任务时停放线程
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let _enter = Enter::new(&self.shared);
+        let signal = ParkSignal::new();
+        let waker = Waker::from(signal.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            if signal.take() {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+
+            // 每轮最多执行当前已入队的任务，之后回头检查主 future，避免互相唤醒的任务让它饿死
+            let receiver = &self.shared.receiver;
+            let mut ran = false;
+            for _ in 0..receiver.len() {
+                let Ok(runnable) = receiver.try_recv() else { break };
+                let _ = catch_unwind(|| runnable.run());
+                ran = true;
+            }
+
+            // 入队和唤醒都会 unpark，检查之后才到达的唤醒不会丢失
+            if !ran && !signal.is_woken() && receiver.is_empty() {
+                thread::park();
+            }
+        }
+    }
+}
+
+impl Drop for LocalExecutor {
+    // 关闭队列并唤醒所有空闲任务，在本线程丢弃全部 Runnable，!Send 的 future 只能在创建它的线程上析构；
+    // 其他线程上与 wake_all 并发的唤醒可能稍后才入队，一直排空到所有任务注销为止，
+    // 否则这些 Runnable 会留在队列里，与持有 Shared 的调度闭包形成循环引用而泄漏
+    fn drop(&mut self) {
+        let shared = &self.shared;
+        shared.closed.store(true, Ordering::Release);
+        shared.registry.wake_all();
+        loop {
+            while let Ok(runnable) = shared.receiver.try_recv() {
+                drop(runnable);
+            }
+            if shared.registry.len() == 0 {
+                break;
+            }
+            thread::park_timeout(Duration::from_millis(1));
+        }
+    }
+}
+
+// 在当前 LocalExecutor::block_on 中提交 !Send 的 future，不在 block_on 中时 panic
+#[track_caller]
+pub fn spawn_local<F, T>(future: F) -> JoinHandle<T>
+    where F: Future<Output = T> + 'static,
+    T: 'static
+{
+    let location = Location::caller();
+    let shared = CURRENT
+        .with(|current| current.borrow().clone())
+        .expect("spawn_local must be called inside LocalExecutor::block_on");
+    spawn_on(&shared, location, future)
+}
+
+fn spawn_on<F, T>(shared: &Arc<Shared>, location: &'static Location<'static>, future: F) -> JoinHandle<T>
+    where F: Future<Output = T> + 'static,
+    T: 'static
+{
+    let schedule_shared = shared.clone();
+    spawn_local_task(&shared.registry, location, future, move |runnable| schedule_shared.schedule(runnable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::future::yield_now;
+    use std::cell::Cell;
+    use std::sync::Mutex;
+
+    // 析构时计数，用来确认 future 在本线程上被丢弃
+    struct DropCount(Rc<Cell<usize>>);
+
+    impl Drop for DropCount {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn runs_non_send_futures() {
+        let executor = LocalExecutor::new();
+        let shared = Rc::new(RefCell::new(Vec::new()));
+        let total = executor.block_on(async {
+            let tasks: Vec<_> = (0..3)
+                .map(|id| {
+                    let shared = shared.clone();
+                    spawn_local(async move {
+                        yield_now().await;
+                        shared.borrow_mut().push(id);
+                        id
+                    })
+                })
+                .collect();
+            let mut total = 0;
+            for task in tasks {
+                total += task.await.unwrap();
+            }
+            total
+        });
+        assert_eq!(total, 3);
+        let mut seen = shared.borrow().clone();
+        seen.sort();
+        assert_eq!(seen, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn tasks_spawned_before_block_on_run_inside_it() {
+        let executor = LocalExecutor::new();
+        let counter = Rc::new(Cell::new(0));
+        let task = {
+            let counter = counter.clone();
+            executor.spawn_local(async move { counter.set(counter.get() + 1) })
+        };
+        assert_eq!(counter.get(), 0);
+        executor.block_on(task).unwrap();
+        assert_eq!(counter.get(), 1);
+    }
+
+    #[test]
+    fn panicking_local_task_returns_join_error() {
+        let executor = LocalExecutor::new();
+        let error = executor.block_on(executor.spawn_local(async { panic!("local boom") })).unwrap_err();
+        assert!(error.is_panic());
+        // 执行器在 panic 之后仍然可用
+        assert_eq!(executor.block_on(executor.spawn_local(async { 1 })).unwrap(), 1);
+    }
+
+    #[test]
+    fn drop_releases_pending_tasks_on_this_thread() {
+        let drops = Rc::new(Cell::new(0));
+        let executor = LocalExecutor::new();
+        let shared = Arc::downgrade(&executor.shared);
+
+        // 一个在 block_on 中挂起的任务，一个从未被 poll 的任务
+        let idle = {
+            let guard = DropCount(drops.clone());
+            executor.spawn_local(async move {
+                let _guard = guard;
+                std::future::pending::<()>().await
+            })
+        };
+        executor.block_on(yield_now());
+        let queued = {
+            let guard = DropCount(drops.clone());
+            executor.spawn_local(async move { drop(guard) })
+        };
+
+        drop(executor);
+        assert_eq!(drops.get(), 2);
+        assert!(futures_lite::future::block_on(idle).unwrap_err().is_cancelled());
+        assert!(futures_lite::future::block_on(queued).unwrap_err().is_cancelled());
+        // 调度闭包与队列之间没有残留的循环引用
+        assert!(shared.upgrade().is_none());
+    }
+
+    #[test]
+    fn wakes_from_other_threads_during_drop_do_not_leak() {
+        for _ in 0..20 {
+            let executor = LocalExecutor::new();
+            let shared = Arc::downgrade(&executor.shared);
+            let slot: Arc<Mutex<Option<Waker>>> = Arc::new(Mutex::new(None));
+            let task = {
+                let slot = slot.clone();
+                executor.spawn_local(std::future::poll_fn(move |cx| {
+                    *slot.lock().unwrap() = Some(cx.waker().clone());
+                    Poll::<()>::Pending
+                }))
+            };
+            executor.block_on(yield_now());
+
+            // 另一个线程不停地唤醒任务，与 drop 中的 wake_all 竞争
+            let waker = slot.lock().unwrap().take().unwrap();
+            let barrier = Arc::new(std::sync::Barrier::new(2));
+            let waking = {
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..1000 {
+                        waker.wake_by_ref();
+                    }
+                })
+            };
+            barrier.wait();
+            drop(executor);
+            waking.join().unwrap();
+
+            drop(task);
+            assert!(shared.upgrade().is_none());
+        }
+    }
+}
@@ -121,10 +121,19 @@
 //     future::block_on(task3);
 // }
 
-use ch03_future_task_queue::{multi_task, sing_task, multi_task_runtime};
+// sing_task / multi_task 通过注释切换运行
+#[allow(unused_imports)]
+use ch03_future_task_queue::{multi_task, sing_task, multi_task_runtime, stealing_task, per_core_task, local_task, sim_task, scope_task, sync_task, channel_task};
 
 fn main() {
     // sing_task();
     // multi_task();
+    // stealing_task();
+    // per_core_task();
+    // local_task();
+    // sim_task();
+    // scope_task();
+    // sync_task();
+    // channel_task();
     multi_task_runtime();
 }
\ No newline at end of file
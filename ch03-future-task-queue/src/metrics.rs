@@ -0,0 +1,393 @@
+//! 运行时指标
+//! worker 执行 Runnable 时记录 poll 次数、耗时和忙/闲时间，任务结束时记录结果与 poll 次数，
+//! Runtime::metrics() 返回某一时刻的快照，用于排查任务卡住或 worker 饥饿；
+//! 单次 poll 超过阈值的任务会打印警告，并可选地汇总成最慢任务报告
+
+use crate::task::TaskInfo;
+use async_task::Runnable;
+use log::warn;
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::panic::{catch_unwind, Location};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// poll 耗时直方图的桶上界（微秒），最后一个桶收集超过最大上界的样本
+const POLL_DURATION_BOUNDS_US: &[u64] = &[10, 100, 1_000, 10_000, 100_000, 1_000_000];
+// 单个任务 poll 次数直方图的桶上界
+const POLLS_PER_TASK_BOUNDS: &[u64] = &[1, 2, 4, 8, 16, 64, 256];
+
+struct AtomicHistogram {
+    bounds: &'static [u64],
+    counts: Vec<AtomicU64>,
+}
+
+impl AtomicHistogram {
+    fn new(bounds: &'static [u64]) -> Self {
+        AtomicHistogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn record(&self, value: u64) {
+        let index = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        Histogram {
+            bounds: self.bounds.to_vec(),
+            counts: self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect(),
+        }
+    }
+}
+
+// 直方图快照：counts[i] 是不超过 bounds[i] 的样本数，最后一个元素是超过所有上界的样本数
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    pub bounds: Vec<u64>,
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+struct WorkerMetrics {
+    name: String,
+    polls: AtomicU64,
+    busy_ns: AtomicU64,
+    idle_ns: AtomicU64,
+    // 正在停放时为停放开始时刻（相对 Metrics::started 的纳秒数加一），否则为 0
+    parked_at: AtomicU64,
+}
+
+// 单个 worker 线程的统计
+#[derive(Debug, Clone)]
+pub struct WorkerSnapshot {
+    pub name: String,
+    pub polls: u64,
+    // 执行 Runnable 的累计时间
+    pub busy: Duration,
+    // 队列为空停放的累计时间
+    pub idle: Duration,
+}
+
+// Runtime::metrics() 返回的快照
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeMetrics {
+    // 各优先级当前排队的任务数，下标 0 为最高级
+    pub queue_depths: Vec<usize>,
+    pub spawned: u64,
+    pub completed: u64,
+    pub panicked: u64,
+    // 被 abort 或随运行时关闭被丢弃的任务
+    pub cancelled: u64,
+    pub live_tasks: usize,
+    pub polls: u64,
+    pub poll_duration_us: Histogram,
+    pub polls_per_task: Histogram,
+    pub workers: Vec<WorkerSnapshot>,
+}
+
+// 单次 poll 超过阈值的任务
+#[derive(Debug, Clone)]
+pub struct SlowTask {
+    pub id: usize,
+    pub name: Option<String>,
+    pub location: &'static Location<'static>,
+    // 超过阈值的 poll 次数
+    pub slow_polls: u64,
+    pub max_poll: Duration,
+    pub total_slow: Duration,
+}
+
+pub(crate) struct Metrics {
+    started: Instant,
+    // None 时不检测慢 poll
+    slow_poll_threshold: Option<Duration>,
+    // 开启报告时按任务 id 汇总慢 poll
+    slow_tasks: Option<Mutex<HashMap<usize, SlowTask>>>,
+    spawned: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    poll_duration_us: AtomicHistogram,
+    polls_per_task: AtomicHistogram,
+    workers: Vec<WorkerMetrics>,
+}
+
+impl Metrics {
+    pub(crate) fn new(worker_names: &[String], slow_poll_threshold: Option<Duration>, slow_poll_report: bool) -> Self {
+        Metrics {
+            started: Instant::now(),
+            slow_poll_threshold,
+            slow_tasks: slow_poll_report.then(|| Mutex::new(HashMap::new())),
+            spawned: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            poll_duration_us: AtomicHistogram::new(POLL_DURATION_BOUNDS_US),
+            polls_per_task: AtomicHistogram::new(POLLS_PER_TASK_BOUNDS),
+            workers: worker_names
+                .iter()
+                .map(|name| WorkerMetrics {
+                    name: name.clone(),
+                    polls: AtomicU64::new(0),
+                    busy_ns: AtomicU64::new(0),
+                    idle_ns: AtomicU64::new(0),
+                    parked_at: AtomicU64::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn task_spawned(&self) {
+        self.spawned.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // 在 worker 上执行一次 poll，记录耗时
+    pub(crate) fn run(&self, worker: usize, runnable: Runnable) {
+        let start = Instant::now();
+        let _ = catch_unwind(|| runnable.run());
+        let elapsed = start.elapsed();
+
+        let worker = &self.workers[worker];
+        worker.polls.fetch_add(1, Ordering::Relaxed);
+        worker.busy_ns.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        self.poll_duration_us.record(elapsed.as_micros() as u64);
+    }
+
+    // 在 block_on 的调用线程上执行一次 poll：只记录耗时分布，不属于任何 worker
+    pub(crate) fn run_outside(&self, runnable: Runnable) {
+        let start = Instant::now();
+        let _ = catch_unwind(|| runnable.run());
+        self.poll_duration_us.record(start.elapsed().as_micros() as u64);
+    }
+
+    fn now_ns(&self) -> u64 {
+        self.started.elapsed().as_nanos() as u64
+    }
+
+    // 停放 worker，记录空闲时间；快照会把尚未结束的停放时间也算进去
+    pub(crate) fn park(&self, worker: usize, park: impl FnOnce()) {
+        let worker = &self.workers[worker];
+        let start = self.now_ns();
+        worker.parked_at.store(start + 1, Ordering::Relaxed);
+        park();
+        worker.parked_at.store(0, Ordering::Relaxed);
+        worker.idle_ns.fetch_add(self.now_ns() - start, Ordering::Relaxed);
+    }
+
+    // 单次 poll 阻塞 worker 超过阈值：打印警告，开启报告时记入该任务的统计
+    fn slow_poll(&self, task: &TaskInfo, elapsed: Duration) {
+        match self.slow_poll_threshold {
+            Some(threshold) if elapsed >= threshold => {
+                warn!(
+                    "task {} ({}) spawned at {} blocked {} for {:?} in a single poll (threshold {:?})",
+                    task.id(),
+                    task.name().unwrap_or("unnamed"),
+                    task.location(),
+                    thread::current().name().unwrap_or("<unnamed>"),
+                    elapsed,
+                    threshold
+                );
+            },
+            _ => return,
+        }
+
+        if let Some(slow_tasks) = &self.slow_tasks {
+            let mut slow_tasks = slow_tasks.lock().unwrap();
+            let slow = slow_tasks.entry(task.id()).or_insert_with(|| SlowTask {
+                id: task.id(),
+                name: task.name().map(str::to_string),
+                location: task.location(),
+                slow_polls: 0,
+                max_poll: Duration::ZERO,
+                total_slow: Duration::ZERO,
+            });
+            slow.slow_polls += 1;
+            slow.max_poll = slow.max_poll.max(elapsed);
+            slow.total_slow += elapsed;
+        }
+    }
+
+    // 包装任务 future：统计 poll 次数和每次 poll 的耗时，结束时按结果计数；被丢弃的任务不会走到这里
+    pub(crate) async fn instrument<F, T>(&self, task: &TaskInfo, future: F) -> thread::Result<Option<T>>
+        where F: Future<Output = thread::Result<Option<T>>>
+    {
+        let mut future = pin!(future);
+        let mut polls = 0;
+        let output = poll_fn(|cx| {
+            polls += 1;
+            let start = Instant::now();
+            let poll = future.as_mut().poll(cx);
+            self.slow_poll(task, start.elapsed());
+            poll
+        })
+        .await;
+
+        self.polls_per_task.record(polls);
+        match &output {
+            Ok(Some(_)) => self.completed.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.panicked.fetch_add(1, Ordering::Relaxed),
+            Ok(None) => 0,
+        };
+        output
+    }
+
+    // 最慢任务报告：按单次 poll 的最长耗时从大到小排序，未开启报告时为空
+    pub(crate) fn slow_tasks(&self) -> Vec<SlowTask> {
+        let mut slow_tasks: Vec<SlowTask> = match &self.slow_tasks {
+            Some(slow_tasks) => slow_tasks.lock().unwrap().values().cloned().collect(),
+            None => Vec::new(),
+        };
+        slow_tasks.sort_by_key(|task| std::cmp::Reverse(task.max_poll));
+        slow_tasks
+    }
+
+    pub(crate) fn snapshot(&self, queue_depths: Vec<usize>, live_tasks: usize) -> RuntimeMetrics {
+        let spawned = self.spawned.load(Ordering::Relaxed);
+        let completed = self.completed.load(Ordering::Relaxed);
+        let panicked = self.panicked.load(Ordering::Relaxed);
+        let now = self.now_ns();
+        let workers: Vec<WorkerSnapshot> = self.workers
+            .iter()
+            .map(|worker| {
+                let parked = match worker.parked_at.load(Ordering::Relaxed) {
+                    0 => 0,
+                    parked_at => now.saturating_sub(parked_at - 1),
+                };
+                WorkerSnapshot {
+                    name: worker.name.clone(),
+                    polls: worker.polls.load(Ordering::Relaxed),
+                    busy: Duration::from_nanos(worker.busy_ns.load(Ordering::Relaxed)),
+                    idle: Duration::from_nanos(worker.idle_ns.load(Ordering::Relaxed) + parked),
+                }
+            })
+            .collect();
+
+        RuntimeMetrics {
+            queue_depths,
+            spawned,
+            completed,
+            panicked,
+            // 各计数器不是同时读取的，用饱和减法避免瞬时不一致导致下溢
+            cancelled: spawned.saturating_sub(completed + panicked + live_tasks as u64),
+            live_tasks,
+            polls: workers.iter().map(|worker| worker.polls).sum(),
+            poll_duration_us: self.poll_duration_us.snapshot(),
+            polls_per_task: self.polls_per_task.snapshot(),
+            workers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commons::Runtime;
+    use crate::multi_worker_queue::Builder;
+    use crate::timer;
+
+    #[test]
+    fn histogram_buckets_by_upper_bound() {
+        let histogram = AtomicHistogram::new(&[10, 100]);
+        for value in [0, 10, 11, 100, 101, 5_000] {
+            histogram.record(value);
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.bounds, vec![10, 100]);
+        assert_eq!(snapshot.counts, vec![2, 2, 2]);
+        assert_eq!(snapshot.total(), 6);
+    }
+
+    #[test]
+    fn snapshot_counts_task_outcomes_and_worker_time() {
+        let mut runtime = Runtime::new().with_high_num(1).with_low_num(1);
+        let handle = runtime.run();
+
+        let completed = handle.spawn(async {
+            timer::sleep(Duration::from_millis(10)).await;
+        });
+        // 一次 poll 阻塞 20ms，落在 10ms ~ 100ms 的桶中
+        let blocking = handle.spawn(async { thread::sleep(Duration::from_millis(20)) });
+        let panicked = handle.spawn(async { panic!("boom") });
+        let aborted = handle.spawn(std::future::pending::<()>());
+        aborted.abort();
+
+        runtime.block_on(async {
+            completed.await.unwrap();
+            blocking.await.unwrap();
+            assert!(panicked.await.unwrap_err().is_panic());
+            assert!(aborted.await.unwrap_err().is_cancelled());
+        });
+        // 让 worker 停放一段时间，空闲时间才会累计
+        thread::sleep(Duration::from_millis(20));
+
+        let metrics = handle.metrics();
+        assert_eq!(metrics.spawned, 4);
+        assert_eq!(metrics.completed, 2);
+        assert_eq!(metrics.panicked, 1);
+        assert_eq!(metrics.cancelled, 1);
+        assert_eq!(metrics.live_tasks, 0);
+        assert_eq!(metrics.queue_depths, vec![0, 0]);
+
+        assert_eq!(metrics.poll_duration_us.total(), metrics.polls);
+        assert!(metrics.poll_duration_us.counts[4] >= 1);
+        // abort 的任务在下一次 poll 中结束，同样记录 poll 次数
+        assert_eq!(metrics.polls_per_task.total(), 4);
+
+        assert_eq!(metrics.workers.len(), 2);
+        assert_eq!(metrics.workers.iter().map(|worker| worker.polls).sum::<u64>(), metrics.polls);
+        assert!(metrics.workers.iter().any(|worker| worker.busy >= Duration::from_millis(20)));
+        assert!(metrics.workers.iter().all(|worker| worker.idle > Duration::ZERO));
+        runtime.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn slow_poll_detection_is_off_without_threshold() {
+        let mut runtime = Runtime::new().with_slow_poll_report(true);
+        let handle = runtime.run();
+        runtime.block_on(handle.spawn(async { thread::sleep(Duration::from_millis(30)) })).unwrap();
+        assert!(handle.slow_tasks().is_empty());
+        runtime.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn slow_tasks_report_worst_offender_first() {
+        let mut runtime = Runtime::new()
+            .with_slow_poll_threshold(Some(Duration::from_millis(10)))
+            .with_slow_poll_report(true);
+        let handle = runtime.run();
+
+        let fast = Builder::new().name("fast").spawn_on(async {}, &handle);
+        let slow = Builder::new().name("slow").spawn_on(
+            async {
+                thread::sleep(Duration::from_millis(20));
+                futures_lite::future::yield_now().await;
+                thread::sleep(Duration::from_millis(20));
+            },
+            &handle,
+        );
+        let slowest = Builder::new().name("slowest").spawn_on(async { thread::sleep(Duration::from_millis(50)) }, &handle);
+        runtime.block_on(async {
+            fast.await.unwrap();
+            slow.await.unwrap();
+            slowest.await.unwrap();
+        });
+
+        let report = handle.slow_tasks();
+        let names: Vec<_> = report.iter().map(|task| task.name.as_deref().unwrap()).collect();
+        assert_eq!(names, vec!["slowest", "slow"]);
+        assert_eq!(report[0].slow_polls, 1);
+        assert!(report[0].max_poll >= Duration::from_millis(50));
+        assert_eq!(report[1].slow_polls, 2);
+        assert!(report[1].total_slow >= Duration::from_millis(40));
+        runtime.shutdown(Duration::from_secs(1));
+    }
+}
@@ -1,206 +1,1358 @@
+//! 一个 QUEUE -> 多个线程处理
+//! 多个 QUEUE
+
 use log::{error, info};
-// #[macro_use]
-// mod crate::commons;
-use crate::commons::{FutureType, HIGH_CHANNEL, LOW_CHANNEL, Runtime};
-use std::{future::Future, panic::catch_unwind, thread};
-use std::pin::Pin;
-use std::task::{Context, Poll};
-use std::time::Duration;
-use async_task::{Runnable, Task};
-use std::sync::LazyLock;
-
-///! 一个 QUEUE -> 多个线程处理
-///! 多个 QUEUE
-
-
-// 队列：static 修饰确保其生命周期和程序一样长
-// LazyLock 只会被初始化一次
-static HIGH_QUEUE: LazyLock<flume::Sender<Runnable>> = LazyLock::new(|| {
-    // let (tx, rx) = flume::unbounded::<Runnable>();
-
-    // // 增加在队列中工作的线程数：创建 3 个线程
-    // for _ in 0..2 {
-    //     let rx = rx.clone();
-    //     thread::spawn(move || {
-    //         while let Ok(runnable) = rx.recv() {
-    //             println!("runnable accpted");
-    //             // 此处使用 catch_unwind 捕获 panic 是因为不知道传递给异步运行时的代码质量
-    //             // 它将捕获代码抛出的任何错误，可根据结果返回 Ok(x) 或 Err(e)
-    //             let _ = catch_unwind(|| runnable.run());
-    //         }
-    //     });
-    // }
-    
-    // tx
-
-    let high_num = std::env::var("HIGH_NUM").unwrap().parse::<usize>().unwrap();
-    for _ in 0..high_num  {
-        let high_receiver = HIGH_CHANNEL.1.clone();
-        let low_receiver = LOW_CHANNEL.1.clone();
-
-        thread::spawn(move || {
-            loop {
-                match high_receiver.try_recv() {
-                    Ok(runnable) => {
-                        let _ = catch_unwind(|| runnable.run());
-                    },
-                    Err(_) => {
-                        match low_receiver.try_recv() {
-                            Ok(runnable) => {
-                                let _ = catch_unwind(|| runnable.run());
-                            },
-                            Err(_) => {
-                                //TODO：第十章会对此进行优化，使用更响应机制的 thread parking 和 condition variables
-                                std::thread::sleep(Duration::from_millis(100));
-                            }
-                        }
-                    }
-                }
-                
-            }
-        });
+use crate::commons::{FutureType, Runtime};
+use crate::affinity;
+use crate::blocking::BlockingPool;
+use crate::metrics::{Metrics, RuntimeMetrics, SlowTask};
+use crate::per_core_queue::PerCoreQueue;
+use crate::reactor::Reactor;
+use crate::task::{abortable, next_task_id, JoinHandle, ShutdownReport, SpawnError, TaskDump, TaskRegistry};
+use crate::task_local;
+use crate::timer::{self, Sleep, TimerDriver};
+use crate::trace::Tracer;
+use crate::work_stealing_queue::StealingQueue;
+use std::{future::Future, thread::{self, Thread}};
+use std::cell::RefCell;
+use std::panic::{AssertUnwindSafe, Location};
+use std::pin::{pin, Pin};
+use std::task::{ready, Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::path::Path;
+use async_task::Runnable;
+use futures_lite::FutureExt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+// 空闲 worker 的停放信号：队列为空时 worker 阻塞在条件变量上，
+// 有 Runnable 入队（spawn_task 或 waker 重新调度）时被精确唤醒，取代原来的 sleep 轮询
+pub(crate) struct WorkerSignal {
+    lock: Mutex<()>,
+    condvar: Condvar,
+}
+
+impl WorkerSignal {
+    pub(crate) const fn new() -> Self {
+        WorkerSignal {
+            lock: Mutex::new(()),
+            condvar: Condvar::new(),
+        }
     }
 
-    HIGH_CHANNEL.0.clone()
-});
-
-static LOW_QUEUE: LazyLock<flume::Sender<Runnable>> = LazyLock::new(|| {
-    // let (tx, rx) = flume::unbounded::<Runnable>();
-
-    // // 增加在队列中工作的线程数：创建 3 个线程
-    // for _ in 0..1 {
-    //     let rx = rx.clone();
-    //     thread::spawn(move || {
-    //         while let Ok(runnable) = rx.recv() {
-    //             println!("runnable accpted");
-    //             // 此处使用 catch_unwind 捕获 panic 是因为不知道传递给异步运行时的代码质量
-    //             // 它将捕获代码抛出的任何错误，可根据结果返回 Ok(x) 或 Err(e)
-    //             let _ = catch_unwind(|| runnable.run());
-    //         }
-    //     });
-    // }
-    
-    // tx
-
-    for _ in 0..1  {   
-        // let high_receiver = LOW_CHANNEL.1.clone();
-        let low_receiver = LOW_CHANNEL.1.clone();
-        thread::spawn(move || {
-            match low_receiver.try_recv() {
-                Ok(runnable) => { 
-                    let _ = catch_unwind(|| runnable.run()); 
-                },
-                Err(_) => {
-                    // match high_receiver.try_recv() {
-                    //     Ok(runnable) => { 
-                    //         let _ = catch_unwind(|| runnable.run()); 
-                    //     },
-                    //     Err(_) => {
-                            //TODO：第十章会对此进行优化，使用更响应机制的 thread parking 和 condition variables
-                            std::thread::sleep(Duration::from_millis(100));
-                    //     }
-                    // }
-                },
+    // 入队之后调用：先拿锁再通知，保证不会和正在检查队列的 worker 错过唤醒
+    pub(crate) fn notify_one(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.condvar.notify_one();
+    }
+
+    // 关闭运行时：唤醒所有停放的 worker，让它们检查停止标志后退出
+    pub(crate) fn notify_all(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    // 持锁检查队列是否为空，为空才停放，避免"检查后、等待前"入队导致的丢失唤醒
+    pub(crate) fn wait_while<F: Fn() -> bool>(&self, is_empty: F) {
+        let guard = self.lock.lock().unwrap();
+        if is_empty() {
+            let _guard = self.condvar.wait(guard).unwrap();
+        }
+    }
+}
+
+// block_on 所在线程的 waker：设置标志并唤醒被停放的线程
+pub(crate) struct ParkSignal {
+    woken: AtomicBool,
+    thread: Thread,
+}
+
+impl ParkSignal {
+    // 初始为已唤醒，保证 future 至少被 poll 一次
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(ParkSignal {
+            woken: AtomicBool::new(true),
+            thread: thread::current(),
+        })
+    }
+
+    // 取走唤醒标志
+    pub(crate) fn take(&self) -> bool {
+        self.woken.swap(false, Ordering::AcqRel)
+    }
+
+    pub(crate) fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Acquire)
+    }
+}
+
+impl Wake for ParkSignal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.thread.unpark();
+    }
+}
+
+// 有界模式下的排队计数：新任务入队前占用一个位置，Runnable 被取出执行时归还
+pub(crate) struct Capacity {
+    // 无界模式为 usize::MAX
+    limit: usize,
+    queued: AtomicUsize,
+    // 等待空位的 spawn_async
+    waiters: Mutex<Vec<Waker>>,
+}
+
+impl Capacity {
+    fn new(limit: Option<usize>) -> Self {
+        Capacity {
+            limit: limit.unwrap_or(usize::MAX),
+            queued: AtomicUsize::new(0),
+            waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    // 不检查容量直接占用：waker 的重新调度和 spawn 总是被接受
+    fn occupy(&self) {
+        self.queued.fetch_add(1, Ordering::AcqRel);
+    }
+
+    fn try_occupy(&self) -> bool {
+        self.queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |queued| (queued < self.limit).then_some(queued + 1))
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+        if self.limit != usize::MAX {
+            self.wake_all();
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        let mut waiters = self.waiters.lock().unwrap();
+        if !waiters.iter().any(|w| w.will_wake(waker)) {
+            waiters.push(waker.clone());
+        }
+    }
+
+    // 空出位置或运行时关闭时唤醒所有等待者，由它们重新竞争，避免被取消的等待者吞掉唤醒
+    fn wake_all(&self) {
+        let waiters = std::mem::take(&mut *self.waiters.lock().unwrap());
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+// worker 循环需要的运行时状态
+pub(crate) struct WorkerContext<'a> {
+    index: usize,
+    stopped: &'a AtomicBool,
+    metrics: &'a Metrics,
+    capacity: &'a Capacity,
+}
+
+impl WorkerContext<'_> {
+    pub(crate) fn index(&self) -> usize {
+        self.index
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    // Runnable 已经出队：归还位置后执行
+    pub(crate) fn run(&self, runnable: Runnable) {
+        self.capacity.release();
+        self.metrics.run(self.index, runnable);
+    }
+
+    pub(crate) fn park(&self, park: impl FnOnce()) {
+        self.metrics.park(self.index, park);
+    }
+}
+
+// 排队中的任务及其入队时间，用于计算老化后的有效优先级
+struct Entry {
+    enqueued: Instant,
+    runnable: Runnable,
+}
+
+// 按优先级划分的全局队列：HIGH worker 处理所有级别，停放在 high_signal 上；LOW worker 只处理最低级
+pub(crate) struct PriorityQueue {
+    levels: Mutex<Vec<VecDeque<Entry>>>,
+    aging: Duration,
+    high_signal: WorkerSignal,
+    low_signal: WorkerSignal,
+}
+
+impl PriorityQueue {
+    pub(crate) fn new(levels: usize, aging: Duration) -> Self {
+        PriorityQueue {
+            levels: Mutex::new((0..levels.max(1)).map(|_| VecDeque::new()).collect()),
+            aging,
+            high_signal: WorkerSignal::new(),
+            low_signal: WorkerSignal::new(),
+        }
+    }
+
+    pub(crate) fn levels(&self) -> usize {
+        self.levels.lock().unwrap().len()
+    }
+
+    pub(crate) fn schedule(&self, runnable: Runnable, level: usize) {
+        let lowest = {
+            let mut levels = self.levels.lock().unwrap();
+            levels[level].push_back(Entry { enqueued: Instant::now(), runnable });
+            levels.len() - 1
+        };
+
+        // 唤醒停放的 worker：最低级任务既可能被 LOW worker 也可能被空闲的 HIGH worker 处理
+        if level == lowest {
+            self.low_signal.notify_one();
+        }
+        self.high_signal.notify_one();
+    }
+
+    // 各级队首是该级等待最久的任务：每等待一个老化周期有效级别减一，取有效级别最小的，
+    // 相同时等待更久的优先，提升到最高级的任务不会被新到的高优先级任务一直压住
+    fn pop(&self, is_high: bool) -> Option<Runnable> {
+        let mut levels = self.levels.lock().unwrap();
+        if !is_high {
+            return levels.last_mut().unwrap().pop_front().map(|entry| entry.runnable);
+        }
+
+        let now = Instant::now();
+        let (_, _, level) = levels
+            .iter()
+            .enumerate()
+            .filter_map(|(level, queue)| {
+                let entry = queue.front()?;
+                let promoted = if self.aging.is_zero() {
+                    0
+                } else {
+                    (now.duration_since(entry.enqueued).as_nanos() / self.aging.as_nanos()) as usize
+                };
+                Some((level.saturating_sub(promoted), entry.enqueued, level))
+            })
+            .min()?;
+        levels[level].pop_front().map(|entry| entry.runnable)
+    }
+
+    fn is_empty(&self, is_high: bool) -> bool {
+        let levels = self.levels.lock().unwrap();
+        if is_high {
+            levels.iter().all(VecDeque::is_empty)
+        } else {
+            levels.last().unwrap().is_empty()
+        }
+    }
+
+    pub(crate) fn run_worker(&self, worker: &WorkerContext<'_>, is_high: bool) {
+        let signal = if is_high { &self.high_signal } else { &self.low_signal };
+
+        while !worker.is_stopped() {
+            if let Some(runnable) = self.pop(is_high) {
+                worker.run(runnable);
+                continue;
             }
-        });
+
+            // 队列为空：停放线程，直到有新的 Runnable 入队
+            worker.park(|| signal.wait_while(|| !worker.is_stopped() && self.is_empty(is_high)));
+        }
     }
-    LOW_CHANNEL.0.clone()
-});
 
-pub trait FutureOrderLabel: Future {
-    fn get_order(&self) -> FutureType;
+    pub(crate) fn wake_all(&self) {
+        self.high_signal.notify_all();
+        self.low_signal.notify_all();
+    }
+
+    // 丢弃仍在排队的 Runnable，对应的任务随之被取消；在锁外 drop，避免任务析构时重入
+    pub(crate) fn drain(&self) {
+        let drained: Vec<Entry> = self.levels.lock().unwrap()
+            .iter_mut()
+            .flat_map(|queue| queue.drain(..))
+            .collect();
+        drop(drained);
+    }
+
+    pub(crate) fn len(&self, level: usize) -> usize {
+        self.levels.lock().unwrap()[level].len()
+    }
 }
 
-pub struct CounterFuture {
-    pub count: u32,
-    // pub order: FutureType,
+// 运行时使用的调度队列：全局优先级队列、工作窃取或 thread-per-core
+pub(crate) enum Queue {
+    Priority(PriorityQueue),
+    Stealing(Box<StealingQueue>),
+    PerCore(PerCoreQueue),
 }
 
-impl Future for CounterFuture {
-    type Output = u32;
+impl Queue {
+    // 新任务的所属核心，只有 thread-per-core 模式使用
+    fn pick_core(&self) -> usize {
+        match self {
+            Queue::PerCore(queue) => queue.pick_core(),
+            _ => 0,
+        }
+    }
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.count += 1;
-        std::thread::sleep(Duration::from_secs(1));
-        println!("CounterFuture poll count :{}", self.count);
-        if self.count < 3 {
-            println!("pending ...");
-            cx.waker().wake_by_ref();
-            Poll::Pending
+    fn schedule(&self, runnable: Runnable, level: usize, core: usize) {
+        match self {
+            Queue::Priority(queue) => queue.schedule(runnable, level),
+            Queue::Stealing(queue) => queue.schedule(runnable, level),
+            Queue::PerCore(queue) => queue.schedule(runnable, level, core),
+        }
+    }
+
+    fn run_worker(&self, worker: &WorkerContext<'_>, is_high: bool) {
+        match self {
+            Queue::Priority(queue) => queue.run_worker(worker, is_high),
+            Queue::Stealing(queue) => queue.run_worker(worker),
+            Queue::PerCore(queue) => queue.run_worker(worker),
+        }
+    }
+
+    // block_on 所在的非 worker 线程帮忙执行任务时取出一个 Runnable；
+    // thread-per-core 模式下任务不离开所属核心，调用线程不帮忙
+    fn steal(&self) -> Option<Runnable> {
+        match self {
+            Queue::Priority(queue) => queue.pop(true),
+            Queue::Stealing(queue) => queue.steal(),
+            Queue::PerCore(_) => None,
+        }
+    }
+
+    fn wake_all(&self) {
+        match self {
+            Queue::Priority(queue) => queue.wake_all(),
+            Queue::Stealing(queue) => queue.wake_all(),
+            Queue::PerCore(queue) => queue.wake_all(),
+        }
+    }
+
+    fn drain(&self) {
+        match self {
+            Queue::Priority(queue) => queue.drain(),
+            Queue::Stealing(queue) => queue.drain(),
+            Queue::PerCore(queue) => queue.drain(),
+        }
+    }
+
+    fn levels(&self) -> usize {
+        match self {
+            Queue::Priority(queue) => queue.levels(),
+            Queue::Stealing(queue) => queue.levels(),
+            Queue::PerCore(queue) => queue.levels(),
+        }
+    }
+
+    fn len(&self, level: usize) -> usize {
+        match self {
+            Queue::Priority(queue) => queue.len(level),
+            Queue::Stealing(queue) => queue.len(level),
+            Queue::PerCore(queue) => queue.len(level),
+        }
+    }
+}
+
+// task-dump 线程检查停止标志的间隔
+const DUMP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+// 写入 dump 的超时：不读取数据的客户端不能一直占住 task-dump 线程，拖住 shutdown 的 join
+const DUMP_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// 运行时自己创建并持有的状态：队列、定时器、reactor 与 worker 线程，不再依赖进程级的 LazyLock
+struct Shared {
+    queue: Queue,
+    registry: Arc<TaskRegistry>,
+    timer: Arc<TimerDriver>,
+    reactor: Arc<Reactor>,
+    blocking: Arc<BlockingPool>,
+    metrics: Arc<Metrics>,
+    // Runtime::with_trace(true) 时记录调度事件
+    tracer: Option<Arc<Tracer>>,
+    capacity: Capacity,
+    // block_on 的调用线程是否帮忙执行排队的任务
+    block_on_help: bool,
+    // 不再接受新的 spawn
+    closed: AtomicBool,
+    // worker 退出，重新调度的 Runnable 直接丢弃
+    stopped: AtomicBool,
+    // worker 线程、定时器线程和 reactor 线程
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl Shared {
+    fn queue_depths(&self) -> Vec<usize> {
+        (0..self.queue.levels()).map(|level| self.queue.len(level)).collect()
+    }
+
+    // waker 重新调度：不受容量限制，任务不会因为队列满而丢失
+    fn schedule(&self, runnable: Runnable, level: usize, core: usize) {
+        if self.stopped.load(Ordering::Acquire) {
+            // 运行时已停止：丢弃 Runnable 即取消任务，而不是让它永远留在队列里
+            return;
+        }
+        self.capacity.occupy();
+        self.queue.schedule(runnable, level, core);
+    }
+
+    // 新任务入队，位置已经在 spawn 时占好
+    fn enqueue(&self, runnable: Runnable, level: usize, core: usize) {
+        if self.stopped.load(Ordering::Acquire) {
+            self.capacity.release();
+            return;
+        }
+        self.queue.schedule(runnable, level, core);
+    }
+
+    fn try_reserve(&self) -> Result<(), SpawnError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(SpawnError::Shutdown);
+        }
+        if self.capacity.try_occupy() {
+            Ok(())
         } else {
-            Poll::Ready(self.count)
+            Err(SpawnError::Full)
+        }
+    }
+
+    fn poll_reserve(&self, cx: &mut Context<'_>) -> Poll<Result<(), SpawnError>> {
+        match self.try_reserve() {
+            Err(SpawnError::Full) => {},
+            result => return Poll::Ready(result),
+        }
+        self.capacity.register(cx.waker());
+
+        // 登记之后再试一次，避免错过登记前刚空出的位置
+        match self.try_reserve() {
+            Err(SpawnError::Full) => Poll::Pending,
+            result => Poll::Ready(result),
+        }
+    }
+
+    // block_on 的调用线程帮忙执行出队的 Runnable
+    fn run_outside(&self, runnable: Runnable) {
+        self.capacity.release();
+        self.metrics.run_outside(runnable);
+    }
+
+    // timeout 为 None 时不等待排空，立即停止
+    fn shutdown(&self, timeout: Option<Duration>) -> ShutdownReport {
+        self.closed.store(true, Ordering::Release);
+        // 等待空位的 spawn_async 得到 SpawnError::Shutdown
+        self.capacity.wake_all();
+
+        if let Some(timeout) = timeout {
+            if !self.registry.wait_drained(timeout) {
+                error!("runtime shutdown timed out after {:?}", timeout);
+            }
+        }
+        let pending = self.registry.pending();
+
+        self.stopped.store(true, Ordering::Release);
+        self.queue.wake_all();
+        self.timer.stop();
+        self.reactor.stop();
+        let report = ShutdownReport { pending, blocking: self.blocking.shutdown() };
+
+        // 在 worker 线程上调用 shutdown 时不能 join 自己
+        let current = thread::current().id();
+        for thread in self.threads.lock().unwrap().drain(..) {
+            if thread.thread().id() != current {
+                let _ = thread.join();
+            }
         }
+
+        self.registry.wake_all();
+        self.queue.drain();
+        report
+    }
+}
+
+// 运行时句柄：可克隆，用于向所属运行时提交任务
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+thread_local! {
+    // worker 线程所属运行时的句柄
+    static CURRENT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+}
+
+// block_on 期间把调用线程标记为运行时线程，结束时清除
+struct Enter;
+
+impl Enter {
+    fn new(handle: Handle) -> Self {
+        CURRENT.with(|current| *current.borrow_mut() = Some(handle));
+        Enter
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.borrow_mut().take());
     }
 }
 
-// impl FutureOrderLabel for CounterFuture {
-//     fn get_order(&self) -> FutureType {
-//         self.order
-//     }
-// }
+impl Handle {
+    // 当前 worker 线程所属运行时的句柄，不在 worker 线程上时 panic
+    pub fn current() -> Handle {
+        Self::try_current().expect("Handle::current() must be called from a runtime worker thread")
+    }
+
+    pub fn try_current() -> Option<Handle> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    // 在调用线程上驱动 future 直到完成，worker 线程照常运行；
+    // 期间调用线程被视为运行时的一部分，可以使用 Handle::current()、定时器和 reactor
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        if Self::try_current().is_some() {
+            panic!("Handle::block_on cannot be called from a runtime thread, it would block a worker");
+        }
+        let _enter = Enter::new(self.clone());
+
+        let signal = ParkSignal::new();
+        let waker = Waker::from(signal.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            if signal.take() {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+
+            // 等待期间帮 worker 执行排队的任务，每执行一个就回头检查主 future 是否被唤醒
+            if self.shared.block_on_help {
+                if let Some(runnable) = self.shared.queue.steal() {
+                    self.shared.run_outside(runnable);
+                    continue;
+                }
+            }
+
+            // 唤醒发生在检查之后也没关系：unpark 会让随后的 park 立即返回
+            if !signal.is_woken() {
+                thread::park();
+            }
+        }
+    }
+
+    pub(crate) fn timer(&self) -> Arc<TimerDriver> {
+        self.shared.timer.clone()
+    }
+
+    pub(crate) fn reactor(&self) -> Arc<Reactor> {
+        self.shared.reactor.clone()
+    }
+
+    // 在运行时的阻塞线程池上执行 f，不占用 High/Low worker
+    pub fn spawn_blocking<F, T>(&self, f: F) -> JoinHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static
+    {
+        self.shared.blocking.spawn(f)
+    }
+
+    // 默认按低优先级调度，与 spawn_task_macro! 保持一致；不受 queue_capacity 限制，见 Runtime::with_queue_capacity
+    #[track_caller]
+    pub fn spawn<F, T>(&self, future: F) -> JoinHandle<T>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        self.spawn_with(future, FutureType::Low)
+    }
+
+    // 按 future 自带的优先级调度
+    #[track_caller]
+    pub fn spawn_labeled<F, T>(&self, future: F) -> JoinHandle<T>
+        where F: FutureOrderLabel<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        let order = future.get_order();
+        self.spawn_with(future, order)
+    }
 
+    // future -> task -> queue
+    // 有界模式下也总是入队（可能超过容量），需要背压时使用 try_spawn 或 spawn_async，见 Runtime::with_queue_capacity
+    #[track_caller]
+    pub fn spawn_with<F, T>(&self, future: F, order: FutureType) -> JoinHandle<T>
+        // 'static 保证此函数的生命周期和程序一样长
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        self.spawn_at(future, order, None, Location::caller())
+    }
+
+    fn spawn_at<F, T>(&self, future: F, order: FutureType, name: Option<String>, location: &'static Location<'static>) -> JoinHandle<T>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        if self.shared.closed.load(Ordering::Acquire) {
+            error!("runtime is shutting down, task spawned at {} is cancelled", location);
+            return cancelled(future);
+        }
 
-// future -> task -> queue
-pub fn spawn_task<F, T>(future: F, order: FutureType) -> Task<T> 
-    // 'static 保证此函数的生命周期和程序一样长
+        self.shared.capacity.occupy();
+        self.submit(future, order, name, location)
+    }
+
+    // 队列已满时返回 SpawnError::Full，不阻塞
+    #[track_caller]
+    pub fn try_spawn<F, T>(&self, future: F) -> Result<JoinHandle<T>, SpawnError>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        self.try_spawn_with(future, FutureType::Low)
+    }
+
+    #[track_caller]
+    pub fn try_spawn_with<F, T>(&self, future: F, order: FutureType) -> Result<JoinHandle<T>, SpawnError>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        let location = Location::caller();
+        self.shared.try_reserve()?;
+        Ok(self.submit(future, order, None, location))
+    }
+
+    // 队列已满时等待空位；运行时关闭时返回 SpawnError::Shutdown
+    #[track_caller]
+    pub fn spawn_async<F, T>(&self, future: F) -> impl Future<Output = Result<JoinHandle<T>, SpawnError>> + '_
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        self.spawn_async_with(future, FutureType::Low)
+    }
+
+    // async fn 上的 #[track_caller] 不生效，先在同步部分记录调用位置
+    #[track_caller]
+    pub fn spawn_async_with<F, T>(&self, future: F, order: FutureType) -> impl Future<Output = Result<JoinHandle<T>, SpawnError>> + '_
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        let location = Location::caller();
+        async move {
+            std::future::poll_fn(|cx| self.shared.poll_reserve(cx)).await?;
+            Ok(self.submit(future, order, None, location))
+        }
+    }
+
+    // 包装 future 并入队，调用前已经占好队列位置
+    fn submit<F, T>(&self, future: F, order: FutureType, name: Option<String>, location: &'static Location<'static>) -> JoinHandle<T>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        // 继承父任务中用 scope_inherited 设置的任务局部变量
+        let future = task_local::inherit(future);
+
+        // 支持通过 AbortHandle 取消，并捕获 poll 中的 panic，作为 JoinError 交给等待方
+        let (future, abort) = abortable(future);
+        let future = AssertUnwindSafe(future).catch_unwind();
+
+        // 登记任务，guard 随 future 一起完成或被丢弃
+        let guard = self.shared.registry.register(location, name, Some(order));
+        let id = guard.id();
+        let info = guard.info().clone();
+        self.shared.metrics.task_spawned();
+        let metrics = self.shared.metrics.clone();
+        let span = self.shared.tracer.as_ref().map(|tracer| tracer.spawn(id, info.name(), location));
+        let schedule_tracer = self.shared.tracer.clone();
+        let future = async move {
+            let guard = guard;
+            let future = metrics.instrument(guard.info(), future);
+            match span {
+                Some(mut span) => guard.run(span.instrument(future)).await,
+                None => guard.run(future).await,
+            }
+        };
+
+        // 创建闭包，用于将 future 转换为 runnable；重新入队时把任务标记为 scheduled
+        let level = order.level(self.shared.queue.levels());
+        let core = self.shared.queue.pick_core();
+        let shared = self.shared.clone();
+        let schedule = move |runnable| {
+            info.scheduled();
+            if let Some(tracer) = &schedule_tracer {
+                tracer.schedule(id);
+            }
+            shared.schedule(runnable, level, core)
+        };
+
+        // runnable 和 task 拥有同一个指向 Fufure 的指针
+        let (runnable, task) = async_task::spawn(future, schedule);
+
+        self.shared.enqueue(runnable, level, core);
+
+        info!("QUEUE count by level: {:?}", self.shared.queue_depths());
+
+        JoinHandle::new(task, abort, id)
+    }
+
+    // 运行时当前的指标快照
+    pub fn metrics(&self) -> RuntimeMetrics {
+        let shared = &self.shared;
+        shared.metrics.snapshot(shared.queue_depths(), shared.registry.len())
+    }
+
+    // 单次 poll 超过阈值的任务，按最长一次 poll 从大到小排序；需要 Runtime::with_slow_poll_report(true)
+    pub fn slow_tasks(&self) -> Vec<SlowTask> {
+        self.shared.metrics.slow_tasks()
+    }
+
+    // 所有存活任务的名称、优先级、状态、poll 次数与存活时间
+    pub fn dump(&self) -> TaskDump {
+        self.shared.registry.dump()
+    }
+
+    // 把记录的调度事件写成 Chrome trace-event JSON；需要 Runtime::with_trace(true)
+    pub fn write_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        match &self.shared.tracer {
+            Some(tracer) => tracer.write_file(path.as_ref()),
+            None => Err(io::Error::other("tracing is disabled, enable it with Runtime::with_trace(true)")),
+        }
+    }
+
+    // 在独立线程上以文本形式提供 dump()：每个连接写入一次后关闭，可以用 nc 127.0.0.1 <port> 查看；
+    // 不依赖 worker，运行时卡住时仍然可用。返回实际绑定的地址（端口为 0 时由系统分配）
+    pub fn serve_dump(&self, addr: SocketAddr) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        // 非阻塞 accept，空闲时定期检查运行时是否已经停止，shutdown 可以 join 该线程
+        listener.set_nonblocking(true)?;
+        let shared = Arc::downgrade(&self.shared);
+        let thread = thread::Builder::new()
+            .name("task-dump".to_string())
+            .spawn(move || loop {
+                let Some(shared) = shared.upgrade().filter(|shared| !shared.stopped.load(Ordering::Acquire)) else {
+                    break;
+                };
+                match listener.accept() {
+                    Ok((mut stream, _)) => {
+                        let dump = shared.registry.dump();
+                        drop(shared);
+                        let _ = stream.set_nonblocking(false);
+                        let _ = stream.set_write_timeout(Some(DUMP_WRITE_TIMEOUT));
+                        let _ = write!(stream, "{}", dump);
+                    },
+                    Err(err) => {
+                        // 不持有 Shared 等待，运行时可以在此期间被释放
+                        drop(shared);
+                        if err.kind() != io::ErrorKind::WouldBlock {
+                            error!("task dump accept failed: {}", err);
+                        }
+                        thread::sleep(DUMP_POLL_INTERVAL);
+                    },
+                }
+            })?;
+        self.shared.threads.lock().unwrap().push(thread);
+        Ok(local_addr)
+    }
+}
+
+// 带选项的 spawn：
+// Builder::new().name("crawler").priority(FutureType::High).spawn_on(future, &handle)
+#[derive(Debug, Clone)]
+pub struct Builder {
+    name: Option<String>,
+    order: FutureType,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    // 默认无名称、低优先级，与 Handle::spawn 一致
+    pub fn new() -> Self {
+        Builder { name: None, order: FutureType::Low }
+    }
+
+    // 名称出现在 dump()、慢 poll 警告和关闭报告中
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn priority(mut self, order: FutureType) -> Self {
+        self.order = order;
+        self
+    }
+
+    #[track_caller]
+    pub fn spawn_on<F, T>(self, future: F, handle: &Handle) -> JoinHandle<T>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        handle.spawn_at(future, self.order, self.name, Location::caller())
+    }
+
+    // 提交到当前 worker 线程所属的运行时，不在 worker 线程上时 panic
+    #[track_caller]
+    pub fn spawn<F, T>(self, future: F) -> JoinHandle<T>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        self.spawn_on(future, &Handle::current())
+    }
+
+    // 队列已满时返回 SpawnError::Full，不阻塞
+    #[track_caller]
+    pub fn try_spawn_on<F, T>(self, future: F, handle: &Handle) -> Result<JoinHandle<T>, SpawnError>
+        where F: Future<Output = T> + Send + 'static,
+        T: Send + 'static
+    {
+        let location = Location::caller();
+        handle.shared.try_reserve()?;
+        Ok(handle.submit(future, self.order, self.name, location))
+    }
+}
+
+// 运行时正在关闭：不再调度，丢弃 Runnable 后返回的 JoinHandle 得到 JoinError::Cancelled
+fn cancelled<F, T>(future: F) -> JoinHandle<T>
     where F: Future<Output = T> + Send + 'static,
     T: Send + 'static
 {
-    // 创建闭包，用于将 future 转换为 runnable
-    // let schedule_high = |runnable| HIGH_QUEUE.send(runnable).unwrap();
-    // let schedule_low = |runnable| LOW_QUEUE.send(runnable).unwrap();
-
-    // let schedule = match order {
-    //     FutureType::High => schedule_high,
-    //     FutureType::Low => schedule_low
-    // };
-    // ---------- 优化如下 ---------
-    
-    let queue = match order {
-        FutureType::High => &HIGH_QUEUE,
-        FutureType::Low => &LOW_QUEUE,
-    };
+    let (future, abort) = abortable(future);
+    let future = AssertUnwindSafe(future).catch_unwind();
+    let (runnable, task) = async_task::spawn(future, |_| {});
+    drop(runnable);
+    JoinHandle::new(task, abort, next_task_id())
+}
+
+// 自带优先级的 future：spawn_labeled 按 get_order() 路由，不需要调用方再传 FutureType
+pub trait FutureOrderLabel: Future {
+    fn get_order(&self) -> FutureType;
+}
+
+// 给任意 future 附加优先级，future 内联保存，不额外分配
+pub struct WithPriority<F> {
+    future: F,
+    order: FutureType,
+}
+
+impl<F: Future> Future for WithPriority<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: future 字段是结构化固定的：只通过这里的 Pin 访问，WithPriority 没有 Drop 实现，也不会把它移出
+        unsafe { self.map_unchecked_mut(|this| &mut this.future) }.poll(cx)
+    }
+}
+
+impl<F: Future> FutureOrderLabel for WithPriority<F> {
+    fn get_order(&self) -> FutureType {
+        self.order
+    }
+}
+
+// future.with_priority(FutureType::High)，让优先级随 future 一起传递
+pub trait FutureOrderExt: Future + Sized {
+    fn with_priority(self, order: FutureType) -> WithPriority<Self> {
+        WithPriority { future: self, order }
+    }
+}
+
+impl<F: Future> FutureOrderExt for F {}
 
-    let schedule = |runnable| queue.send(runnable).map_err(|e| {
-        error!("failed to send task: {:?}", e);
-        e
-    }).unwrap();
+// 每次计数前等待 1 秒，计到 3 完成；等待交给定时器，不占住 worker 线程
+#[derive(Default)]
+pub struct CounterFuture {
+    pub count: u32,
+    sleep: Option<Sleep>,
+}
 
-    // -----------------------------------
+impl CounterFuture {
+    pub fn new() -> Self {
+        CounterFuture { count: 0, sleep: None }
+    }
+}
 
-    // runnable 和 task 拥有同一个指向 Fufure 的指针
-    let (runnable, task) = async_task::spawn(future, schedule);
-    
-    runnable.schedule();
+impl Future for CounterFuture {
+    type Output = u32;
 
-    info!("HIGH_QUEUE count: {}", HIGH_QUEUE.len());
-    info!("LOW_QUEUE count: {}", LOW_QUEUE.len());
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        loop {
+            let sleep = self.sleep.get_or_insert_with(|| timer::sleep(Duration::from_secs(1)));
+            ready!(Pin::new(sleep).poll(cx));
+            self.sleep = None;
 
-    task
+            self.count += 1;
+            println!("CounterFuture poll count :{}", self.count);
+            if self.count >= 3 {
+                return Poll::Ready(self.count);
+            }
+            println!("pending ...");
+        }
+    }
 }
 
 #[macro_export]
 macro_rules! spawn_task_macro {
-    ($future:expr, $order: expr) => {
-        // 如何调用当前crate中的函数spawn_task
-        $crate::multi_worker_queue::spawn_task($future, $order)
+    // 命名任务：名称出现在 Runtime::dump() 中
+    ($handle:expr, named $name:expr, labeled $future:expr) => {{
+        let future = $future;
+        let order = $crate::multi_worker_queue::FutureOrderLabel::get_order(&future);
+        $crate::multi_worker_queue::Builder::new().name($name).priority(order).spawn_on(future, &$handle)
+    }};
+    ($handle:expr, named $name:expr, $future:expr, $order: expr) => {
+        $crate::multi_worker_queue::Builder::new().name($name).priority($order).spawn_on($future, &$handle)
+    };
+    ($handle:expr, named $name:expr, $future:expr) => {
+        $crate::multi_worker_queue::Builder::new().name($name).spawn_on($future, &$handle)
+    };
+    // 优先级由 future 自己的 FutureOrderLabel 决定
+    ($handle:expr, labeled $future:expr) => {
+        $handle.spawn_labeled($future)
+    };
+    ($handle:expr, $future:expr, $order: expr) => {
+        $handle.spawn_with($future, $order)
     };
-    ($future:expr) => {
-        spawn_task_macro!($future, FutureType::Low)
+    ($handle:expr, $future:expr) => {
+        $handle.spawn_with($future, $crate::commons::FutureType::Low)
     };
 }
 
 impl Runtime {
-    
-    pub fn run(&self) {
-        std::env::set_var("HIGH_NUM", self.high_num.to_string());
-        std::env::set_var("LOW_NUM", self.low_num.to_string());
+
+    // 创建队列并启动 worker 线程，返回可克隆的 Handle；重复调用返回同一个运行时的句柄
+    pub fn run(&mut self) -> Handle {
+        if let Some(handle) = &self.handle {
+            return handle.clone();
+        }
 
         println!("high_num: {}", self.high_num);
 
-        let high = spawn_task_macro!(async {}, FutureType::High);
-        let low = spawn_task_macro!(async {}, FutureType::Low);
+        // thread-per-core：每个核心一个 worker，不区分 HIGH / LOW
+        let per_core = self.thread_per_core.as_ref().map(|cores| {
+            if cores.is_empty() { affinity::core_ids() } else { cores.clone() }
+        });
+
+        let queue = if let Some(cores) = &per_core {
+            Queue::PerCore(PerCoreQueue::new(cores.len(), self.priority_levels, self.aging))
+        } else if self.work_stealing {
+            Queue::Stealing(Box::new(StealingQueue::new(self.high_num, self.low_num, self.priority_levels)))
+        } else {
+            Queue::Priority(PriorityQueue::new(self.priority_levels, self.aging))
+        };
+
+        // HIGH worker 在前，LOW worker 在后；第 n 个 worker 绑定到对应 CPU 列表的第 n % len 个
+        let high_num = match &per_core {
+            Some(cores) => cores.len(),
+            None => self.high_num,
+        };
+        let (names, pins): (Vec<String>, Vec<Option<usize>>) = match &per_core {
+            Some(cores) => cores
+                .iter()
+                .enumerate()
+                .map(|(index, core)| (format!("core-worker-{}", index), Some(*core)))
+                .unzip(),
+            None => (0..self.high_num + self.low_num)
+                .map(|index| {
+                    let (kind, cores, nth) = if index < high_num {
+                        ("high", &self.high_cores, index)
+                    } else {
+                        ("low", &self.low_cores, index - high_num)
+                    };
+                    let pin = (!cores.is_empty()).then(|| cores[nth % cores.len()]);
+                    (format!("{}-worker-{}", kind, index), pin)
+                })
+                .unzip(),
+        };
 
-        join_future!(high, low);
+        let (timer, timer_thread) = TimerDriver::start();
+        let (reactor, reactor_thread) = Reactor::start().expect("failed to start I/O reactor");
+        let handle = Handle {
+            shared: Arc::new(Shared {
+                queue,
+                registry: Arc::new(TaskRegistry::new()),
+                timer,
+                reactor,
+                blocking: BlockingPool::new(self.max_blocking_threads),
+                metrics: Arc::new(Metrics::new(&names, self.slow_poll_threshold, self.slow_poll_report)),
+                tracer: self.trace.then(|| Arc::new(Tracer::new())),
+                capacity: Capacity::new(self.queue_capacity),
+                block_on_help: self.block_on_help,
+                closed: AtomicBool::new(false),
+                stopped: AtomicBool::new(false),
+                threads: Mutex::new(vec![timer_thread, reactor_thread]),
+            }),
+        };
+
+        let workers: Vec<_> = names
+            .into_iter()
+            .zip(pins)
+            .enumerate()
+            .map(|(index, (name, pin))| {
+                let is_high = index < high_num;
+                let handle = handle.clone();
+                thread::Builder::new()
+                    .name(name)
+                    .spawn(move || {
+                        if let Some(core) = pin {
+                            affinity::pin_current(core);
+                        }
+                        CURRENT.with(|current| *current.borrow_mut() = Some(handle.clone()));
+                        let shared = &handle.shared;
+                        let worker = WorkerContext {
+                            index,
+                            stopped: &shared.stopped,
+                            metrics: &shared.metrics,
+                            capacity: &shared.capacity,
+                        };
+                        shared.queue.run_worker(&worker, is_high);
+                        CURRENT.with(|current| current.borrow_mut().take());
+                    })
+                    .unwrap()
+            })
+            .collect();
+        handle.shared.threads.lock().unwrap().extend(workers);
+
+        if let Some(addr) = self.dump_addr {
+            match handle.serve_dump(addr) {
+                Ok(addr) => info!("task dump served on {}", addr),
+                Err(err) => error!("failed to serve task dump on {}: {}", addr, err),
+            }
+        }
+
+        self.handle = Some(handle.clone());
+        handle
+    }
+
+    // 启动运行时（如果尚未启动），在调用线程上驱动 future 直到完成
+    pub fn block_on<F: Future>(&mut self, future: F) -> F::Output {
+        self.run().block_on(future)
+    }
+
+    // 已启动运行时的句柄
+    pub fn handle(&self) -> Handle {
+        self.handle.clone().expect("Runtime::run() must be called before Runtime::handle()")
+    }
+
+    // 运行时的指标快照，见 RuntimeMetrics
+    pub fn metrics(&self) -> RuntimeMetrics {
+        self.handle().metrics()
+    }
+
+    pub fn slow_tasks(&self) -> Vec<SlowTask> {
+        self.handle().slow_tasks()
+    }
+
+    // 存活任务列表，见 Handle::dump
+    pub fn dump(&self) -> TaskDump {
+        self.handle().dump()
+    }
+
+    // 见 Handle::write_trace
+    pub fn write_trace(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.handle().write_trace(path)
+    }
+
+    // 停止接受新任务，在 timeout 内等待已有任务完成，随后取消剩余任务并 join 所有 worker
+    pub fn shutdown(&mut self, timeout: Duration) -> ShutdownReport {
+        match self.handle.take() {
+            Some(handle) => handle.shared.shutdown(Some(timeout)),
+            None => ShutdownReport::default(),
+        }
+    }
+
+    // 不等待，立即取消所有排队中的任务并 join 所有 worker
+    pub fn shutdown_now(&mut self) -> ShutdownReport {
+        match self.handle.take() {
+            Some(handle) => handle.shared.shutdown(None),
+            None => ShutdownReport::default(),
+        }
+    }
+}
+
+// 没有显式 shutdown 就被丢弃的运行时立即关闭：回收 worker、定时器和 reactor 线程，
+// 并清空队列，打断 Shared -> Runnable -> schedule 闭包 -> Shared 的引用环；
+// 之后仍持有的 Handle 上 spawn 的任务直接被取消
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.shutdown_now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 执行时把 tag 记入 order 的 Runnable
+    fn tagged(tag: usize, order: &Arc<Mutex<Vec<usize>>>) -> Runnable {
+        let order = order.clone();
+        let (runnable, task) = async_task::spawn(async move { order.lock().unwrap().push(tag) }, |_| {});
+        task.detach();
+        runnable
+    }
+
+    fn pop_all(queue: &PriorityQueue) {
+        while let Some(runnable) = queue.pop(true) {
+            runnable.run();
+        }
+    }
+
+    #[test]
+    fn higher_level_wins_without_aging() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let queue = PriorityQueue::new(3, Duration::ZERO);
+        queue.schedule(tagged(2, &order), 2);
+        thread::sleep(Duration::from_millis(20));
+        queue.schedule(tagged(1, &order), 1);
+        queue.schedule(tagged(0, &order), 0);
+
+        pop_all(&queue);
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn aging_promotes_starved_low_priority_task() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let queue = PriorityQueue::new(3, Duration::from_millis(10));
+        queue.schedule(tagged(2, &order), 2);
+        // 等待超过两个老化周期，最低级任务已提升到最高级，且比新到的高优先级任务等待更久
+        thread::sleep(Duration::from_millis(30));
+        queue.schedule(tagged(0, &order), 0);
+        queue.schedule(tagged(1, &order), 1);
+
+        pop_all(&queue);
+        assert_eq!(*order.lock().unwrap(), vec![2, 0, 1]);
+    }
+
+    #[test]
+    fn low_worker_only_takes_lowest_level() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let queue = PriorityQueue::new(2, Duration::ZERO);
+        queue.schedule(tagged(0, &order), 0);
+        assert!(queue.pop(false).is_none());
+        queue.schedule(tagged(1, &order), 1);
+        queue.pop(false).unwrap().run();
+        assert_eq!(*order.lock().unwrap(), vec![1]);
+    }
+
+    // 用阻塞在通道上的任务占住 count 个 worker，drop 返回的 Sender 释放它们
+    fn block_workers(handle: &Handle, count: usize) -> (Vec<JoinHandle<()>>, flume::Sender<()>) {
+        let (started_tx, started_rx) = flume::unbounded();
+        let (release_tx, release_rx) = flume::unbounded::<()>();
+        let blockers = (0..count)
+            .map(|_| {
+                let (started_tx, release_rx) = (started_tx.clone(), release_rx.clone());
+                handle.spawn(async move {
+                    started_tx.send(()).unwrap();
+                    let _ = release_rx.recv();
+                })
+            })
+            .collect();
+        for _ in 0..count {
+            started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        (blockers, release_tx)
+    }
+
+    #[test]
+    fn try_spawn_reports_full_queue_and_shutdown() {
+        let mut runtime = Runtime::new().with_high_num(1).with_low_num(1).with_queue_capacity(1);
+        let handle = runtime.run();
+
+        // 占住两个 worker，之后 spawn 的任务只能留在队列里
+        let (blockers, release_tx) = block_workers(&handle, 2);
+
+        let queued = handle.try_spawn(async { 1 }).unwrap();
+        assert_eq!(handle.try_spawn(async { 2 }).err(), Some(SpawnError::Full));
+        // 普通 spawn 不受容量限制
+        let bypass = handle.spawn(async { 3 });
+
+        drop(release_tx);
+        assert_eq!(runtime.block_on(queued).unwrap(), 1);
+        assert_eq!(runtime.block_on(bypass).unwrap(), 3);
+        for blocker in blockers {
+            runtime.block_on(blocker).unwrap();
+        }
+
+        runtime.shutdown(Duration::from_secs(1));
+        assert_eq!(handle.try_spawn(async { 4 }).err(), Some(SpawnError::Shutdown));
+    }
+
+    // 两个 worker 都被占住时，在 block_on 中等待一个依赖其他任务的任务；
+    // 返回结果以及叶子任务执行时 worker 是否已经被释放
+    fn block_on_with_busy_workers(help: bool) -> (usize, bool) {
+        let mut runtime = Runtime::new().with_high_num(1).with_low_num(1).with_block_on_help(help);
+        let handle = runtime.run();
+
+        let (blockers, release_tx) = block_workers(&handle, 2);
+
+        let released = Arc::new(AtomicBool::new(false));
+        let releaser = {
+            let released = released.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                released.store(true, Ordering::SeqCst);
+                drop(release_tx);
+            })
+        };
+
+        let leaf = {
+            let released = released.clone();
+            move |value: usize| {
+                let released = released.clone();
+                async move { (value, released.load(Ordering::SeqCst)) }
+            }
+        };
+        let parent = handle.spawn(async move {
+            let handle = Handle::current();
+            let left = handle.spawn(leaf(1));
+            let right = handle.spawn(leaf(2));
+            let (left, left_released) = left.await.unwrap();
+            let (right, right_released) = right.await.unwrap();
+            (left + right, left_released || right_released)
+        });
+        let output = handle.block_on(parent).unwrap();
+
+        releaser.join().unwrap();
+        for blocker in blockers {
+            runtime.block_on(blocker).unwrap();
+        }
+        runtime.shutdown(Duration::from_secs(1));
+        output
+    }
+
+    #[test]
+    fn block_on_helps_run_dependent_tasks() {
+        // 调用线程自己执行父任务和它等待的子任务，不需要等 worker 空闲
+        assert_eq!(block_on_with_busy_workers(true), (3, false));
+    }
+
+    #[test]
+    fn block_on_without_help_waits_for_workers() {
+        assert_eq!(block_on_with_busy_workers(false), (3, true));
+    }
+
+    // 自己实现 FutureOrderLabel 的 future
+    struct Urgent;
+
+    impl Future for Urgent {
+        type Output = usize;
+
+        fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<usize> {
+            Poll::Ready(0)
+        }
+    }
+
+    impl FutureOrderLabel for Urgent {
+        fn get_order(&self) -> FutureType {
+            FutureType::High
+        }
+    }
+
+    #[test]
+    fn label_picks_priority_level() {
+        let mut runtime = Runtime::new().with_high_num(1).with_low_num(1).with_priority_levels(3);
+        let handle = runtime.run();
+        let (blockers, release_tx) = block_workers(&handle, 2);
+
+        // worker 都被占住，任务按标签留在对应级别的队列里
+        let urgent = handle.spawn_labeled(Urgent);
+        let middle = handle.spawn_labeled(async { 1 }.with_priority(FutureType::Level(1)));
+        let low = spawn_task_macro!(handle, labeled async { 2 }.with_priority(FutureType::Low));
+        let high = spawn_task_macro!(handle, named "labeled", labeled async { 3 }.with_priority(FutureType::High));
+        assert_eq!(handle.shared.queue_depths(), vec![2, 1, 1]);
+
+        let dump = handle.dump();
+        let priority = |id: usize| dump.tasks.iter().find(|task| task.id == id).unwrap().priority;
+        assert_eq!(priority(urgent.id()), Some(FutureType::High));
+        assert_eq!(priority(middle.id()), Some(FutureType::Level(1)));
+        assert_eq!(priority(low.id()), Some(FutureType::Low));
+        assert_eq!(priority(high.id()), Some(FutureType::High));
+
+        drop(release_tx);
+        assert_eq!(runtime.block_on(urgent).unwrap(), 0);
+        assert_eq!(runtime.block_on(middle).unwrap(), 1);
+        assert_eq!(runtime.block_on(low).unwrap(), 2);
+        assert_eq!(runtime.block_on(high).unwrap(), 3);
+        for blocker in blockers {
+            runtime.block_on(blocker).unwrap();
+        }
+        runtime.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn with_priority_polls_pinned_future_in_place() {
+        // 跨 await 持有自身引用的 future 不是 Unpin，WithPriority 直接在原地 poll 它
+        let future = async {
+            let values = [1, 2, 3];
+            let first = &values[0];
+            futures_lite::future::yield_now().await;
+            *first + values.len()
+        }
+        .with_priority(FutureType::Level(1));
+        assert_eq!(future.get_order(), FutureType::Level(1));
+        assert_eq!(futures_lite::future::block_on(future), 4);
+    }
+
+    #[test]
+    fn serve_dump_lists_live_tasks() {
+        use std::io::Read;
+        use std::net::TcpStream;
+
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+        let task = Builder::new().name("dump-me").spawn_on(std::future::pending::<()>(), &handle);
+        let addr = handle.serve_dump("127.0.0.1:0".parse().unwrap()).unwrap();
+
+        let mut output = String::new();
+        TcpStream::connect(addr).unwrap().read_to_string(&mut output).unwrap();
+        assert!(output.starts_with("1 live tasks"), "{}", output);
+        let line = output.lines().find(|line| line.contains("dump-me")).unwrap();
+        assert_eq!(line.split_whitespace().next(), Some(task.id().to_string().as_str()));
+
+        // 连接后不读取的客户端不会拖住 shutdown
+        let _idle = TcpStream::connect(addr).unwrap();
+        let started = Instant::now();
+        runtime.shutdown_now();
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn shutdown_reports_pending_and_blocking_tasks() {
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+
+        let done = handle.spawn(async { 1 });
+        assert_eq!(runtime.block_on(done).unwrap(), 1);
+        let _stuck = Builder::new().name("stuck").spawn_on(std::future::pending::<()>(), &handle);
+        let _blocking = handle.spawn_blocking(|| thread::sleep(Duration::from_millis(300)));
+        thread::sleep(Duration::from_millis(50));
+
+        let report = runtime.shutdown(Duration::from_millis(50));
+        assert_eq!(report.pending.len(), 1);
+        assert_eq!(report.pending[0].name.as_deref(), Some("stuck"));
+        assert_eq!(report.blocking, 1);
+    }
+
+    #[test]
+    fn shutdown_of_idle_runtime_reports_nothing() {
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+        runtime.block_on(handle.spawn(timer::sleep(Duration::from_millis(10)))).unwrap();
+
+        let report = runtime.shutdown(Duration::from_secs(1));
+        assert!(report.pending.is_empty());
+        assert_eq!(report.blocking, 0);
     }
-}
\ No newline at end of file
+}
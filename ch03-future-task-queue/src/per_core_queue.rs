@@ -0,0 +1,133 @@
+//! thread-per-core 调度
+//! 每个核心一个 worker 和一个独立的优先级队列，worker 绑定到对应 CPU；
+//! 任务在 spawn 时确定所属核心，之后 waker 总是把它放回同一个队列，不会在核心之间迁移
+
+use crate::multi_worker_queue::{PriorityQueue, WorkerContext};
+use async_task::Runnable;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// 区分同一进程中的多个运行时，避免把任务分配给其他运行时的核心
+static NEXT_QUEUE_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // 当前 worker 所属的 (队列 id, 核心下标)
+    static CURRENT_CORE: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+pub(crate) struct PerCoreQueue {
+    id: usize,
+    cores: Vec<PriorityQueue>,
+    // 非 worker 线程 spawn 时按轮转分配核心
+    next: AtomicUsize,
+}
+
+impl PerCoreQueue {
+    pub(crate) fn new(cores: usize, levels: usize, aging: Duration) -> Self {
+        PerCoreQueue {
+            id: NEXT_QUEUE_ID.fetch_add(1, Ordering::Relaxed),
+            cores: (0..cores.max(1)).map(|_| PriorityQueue::new(levels, aging)).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn levels(&self) -> usize {
+        self.cores[0].levels()
+    }
+
+    // 新任务的所属核心：在 worker 上 spawn 的任务留在当前核心，共享同一份缓存
+    pub(crate) fn pick_core(&self) -> usize {
+        match CURRENT_CORE.with(Cell::get) {
+            Some((id, core)) if id == self.id => core,
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % self.cores.len(),
+        }
+    }
+
+    pub(crate) fn schedule(&self, runnable: Runnable, level: usize, core: usize) {
+        self.cores[core].schedule(runnable, level);
+    }
+
+    // 每个 worker 只处理自己核心的队列，空闲时也不从其他核心窃取
+    pub(crate) fn run_worker(&self, worker: &WorkerContext<'_>) {
+        CURRENT_CORE.with(|current| current.set(Some((self.id, worker.index()))));
+        self.cores[worker.index()].run_worker(worker, true);
+        CURRENT_CORE.with(|current| current.set(None));
+    }
+
+    pub(crate) fn wake_all(&self) {
+        self.cores.iter().for_each(PriorityQueue::wake_all);
+    }
+
+    pub(crate) fn drain(&self) {
+        self.cores.iter().for_each(PriorityQueue::drain);
+    }
+
+    // 所有核心队列中该级别的任务数之和
+    pub(crate) fn len(&self, level: usize) -> usize {
+        self.cores.iter().map(|queue| queue.len(level)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commons::Runtime;
+    use crate::timer;
+    use std::thread;
+
+    #[test]
+    fn pick_core_round_robins_outside_workers() {
+        let queue = PerCoreQueue::new(3, 2, Duration::ZERO);
+        let picked: Vec<_> = (0..4).map(|_| queue.pick_core()).collect();
+        assert_eq!(picked, vec![0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn pick_core_keeps_worker_core_of_same_queue() {
+        let queue = PerCoreQueue::new(3, 2, Duration::ZERO);
+        let other = PerCoreQueue::new(3, 2, Duration::ZERO);
+        CURRENT_CORE.with(|current| current.set(Some((queue.id, 2))));
+        assert_eq!(queue.pick_core(), 2);
+        assert_eq!(queue.pick_core(), 2);
+        // 其他运行时的 worker 不影响这个队列的分配
+        assert_eq!(other.pick_core(), 0);
+        CURRENT_CORE.with(|current| current.set(None));
+    }
+
+    fn worker_name() -> String {
+        thread::current().name().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn tasks_and_children_stay_on_their_core() {
+        let mut runtime = Runtime::new().with_thread_per_core(vec![0, 0]);
+        let handle = runtime.run();
+
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let spawner = handle.clone();
+                handle.spawn(async move {
+                    let mut seen = vec![worker_name()];
+                    // 子任务与父任务同核心，每次被定时器唤醒后也回到原来的核心
+                    let child = spawner.spawn(async { worker_name() });
+                    for _ in 0..3 {
+                        timer::sleep(Duration::from_millis(5)).await;
+                        seen.push(worker_name());
+                    }
+                    seen.push(child.await.unwrap());
+                    seen
+                })
+            })
+            .collect();
+
+        let mut cores = Vec::new();
+        for task in tasks {
+            let seen = runtime.block_on(task).unwrap();
+            assert!(seen.iter().all(|name| *name == seen[0]), "task migrated: {:?}", seen);
+            cores.push(seen[0].clone());
+        }
+        assert_eq!(cores, vec!["core-worker-0", "core-worker-1"]);
+        runtime.shutdown(Duration::from_secs(1));
+    }
+}
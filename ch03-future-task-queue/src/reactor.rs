@@ -0,0 +1,416 @@
+//! I/O reactor
+//! 由独立线程持有 mio::Poll，把 Token 映射到等待读/写的 waker，
+//! 套接字就绪时才唤醒对应任务，future 的 poll 中不再阻塞等待事件
+
+use crate::multi_worker_queue::Handle;
+use futures_lite::io::{AsyncRead, AsyncWrite};
+use mio::event::Source;
+use mio::{Events, Interest, Poll as MioPoll, Registry, Token};
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+// 用于关闭时唤醒 reactor 线程
+const WAKE_TOKEN: Token = Token(usize::MAX);
+
+// 就绪状态的低两位是读/写标志，其余位是事件计数：
+// 清除就绪标志时比较计数，避免把清除前刚到达的新事件一起清掉
+const READABLE: usize = 0b01;
+const WRITABLE: usize = 0b10;
+const TICK: usize = 0b100;
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Read,
+    Write,
+}
+
+impl Direction {
+    fn mask(self) -> usize {
+        match self {
+            Direction::Read => READABLE,
+            Direction::Write => WRITABLE,
+        }
+    }
+}
+
+// 每个注册到 reactor 的 I/O 资源对应的就绪状态和等待者
+struct ScheduledIo {
+    readiness: AtomicUsize,
+    reader: Mutex<Option<Waker>>,
+    writer: Mutex<Option<Waker>>,
+}
+
+impl ScheduledIo {
+    fn new() -> Self {
+        ScheduledIo {
+            readiness: AtomicUsize::new(0),
+            reader: Mutex::new(None),
+            writer: Mutex::new(None),
+        }
+    }
+
+    fn waiter(&self, direction: Direction) -> &Mutex<Option<Waker>> {
+        match direction {
+            Direction::Read => &self.reader,
+            Direction::Write => &self.writer,
+        }
+    }
+
+    fn set_readiness(&self, ready: usize) {
+        let _ = self.readiness.fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+            Some((current & !(READABLE | WRITABLE)).wrapping_add(TICK) | (current & (READABLE | WRITABLE)) | ready)
+        });
+
+        for direction in [Direction::Read, Direction::Write] {
+            if ready & direction.mask() != 0 {
+                if let Some(waker) = self.waiter(direction).lock().unwrap().take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    // 就绪时返回观察到的状态；否则登记 waker，登记后再检查一次，避免错过刚到达的事件
+    fn poll_ready(&self, direction: Direction, cx: &mut Context<'_>) -> Poll<usize> {
+        let current = self.readiness.load(Ordering::Acquire);
+        if current & direction.mask() != 0 {
+            return Poll::Ready(current);
+        }
+
+        *self.waiter(direction).lock().unwrap() = Some(cx.waker().clone());
+
+        let current = self.readiness.load(Ordering::Acquire);
+        if current & direction.mask() != 0 {
+            Poll::Ready(current)
+        } else {
+            Poll::Pending
+        }
+    }
+
+    // 操作返回 WouldBlock 时清除就绪标志；期间有新事件到达则计数已变化，保留就绪
+    fn clear_readiness(&self, observed: usize, direction: Direction) {
+        let _ = self.readiness.compare_exchange(
+            observed,
+            observed & !direction.mask(),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+}
+
+pub(crate) struct Reactor {
+    registry: Registry,
+    waker: mio::Waker,
+    ios: Mutex<HashMap<Token, Arc<ScheduledIo>>>,
+    next_token: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+// 不在运行时 worker 线程上创建 I/O 资源时使用的默认 reactor
+static DEFAULT_REACTOR: LazyLock<Arc<Reactor>> = LazyLock::new(|| Reactor::start().unwrap().0);
+
+impl Reactor {
+    pub(crate) fn start() -> io::Result<(Arc<Reactor>, JoinHandle<()>)> {
+        let mut poll = MioPoll::new()?;
+        let reactor = Arc::new(Reactor {
+            registry: poll.registry().try_clone()?,
+            waker: mio::Waker::new(poll.registry(), WAKE_TOKEN)?,
+            ios: Mutex::new(HashMap::new()),
+            next_token: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        });
+
+        let thread_reactor = reactor.clone();
+        let handle = thread::Builder::new()
+            .name("reactor".to_string())
+            .spawn(move || thread_reactor.run(&mut poll))?;
+
+        Ok((reactor, handle))
+    }
+
+    // 当前运行时的 reactor，不在运行时上时退回默认 reactor
+    fn current() -> Arc<Reactor> {
+        match Handle::try_current() {
+            Some(handle) => handle.reactor(),
+            None => DEFAULT_REACTOR.clone(),
+        }
+    }
+
+    fn run(&self, poll: &mut MioPoll) {
+        let mut events = Events::with_capacity(1024);
+        while !self.stopped.load(Ordering::Acquire) {
+            if let Err(err) = poll.poll(&mut events, None) {
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                log::error!("reactor poll failed: {}", err);
+                break;
+            }
+
+            for event in events.iter() {
+                if event.token() == WAKE_TOKEN {
+                    continue;
+                }
+
+                // 关闭和错误也视为就绪，让读写操作自己返回 0 或错误
+                let mut ready = 0;
+                if event.is_readable() || event.is_read_closed() || event.is_error() {
+                    ready |= READABLE;
+                }
+                if event.is_writable() || event.is_write_closed() || event.is_error() {
+                    ready |= WRITABLE;
+                }
+
+                let io = self.ios.lock().unwrap().get(&event.token()).cloned();
+                if let Some(io) = io {
+                    io.set_readiness(ready);
+                }
+            }
+        }
+    }
+
+    fn register<S: Source>(&self, source: &mut S) -> io::Result<(Token, Arc<ScheduledIo>)> {
+        let token = Token(self.next_token.fetch_add(1, Ordering::Relaxed));
+        let io = Arc::new(ScheduledIo::new());
+        self.ios.lock().unwrap().insert(token, io.clone());
+
+        if let Err(err) = self.registry.register(source, token, Interest::READABLE | Interest::WRITABLE) {
+            self.ios.lock().unwrap().remove(&token);
+            return Err(err);
+        }
+        Ok((token, io))
+    }
+
+    fn deregister<S: Source>(&self, source: &mut S, token: Token) {
+        let _ = self.registry.deregister(source);
+        self.ios.lock().unwrap().remove(&token);
+    }
+
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        let _ = self.waker.wake();
+    }
+}
+
+// 注册到 reactor 的 mio 资源，drop 时自动注销
+struct PollEvented<S: Source> {
+    source: S,
+    reactor: Arc<Reactor>,
+    token: Token,
+    io: Arc<ScheduledIo>,
+}
+
+impl<S: Source> PollEvented<S> {
+    fn new(mut source: S) -> io::Result<Self> {
+        let reactor = Reactor::current();
+        let (token, io) = reactor.register(&mut source)?;
+        Ok(PollEvented { source, reactor, token, io })
+    }
+
+    // 等待就绪后执行非阻塞操作，WouldBlock 时清除就绪标志并继续等待
+    fn poll_io<R>(
+        &self,
+        direction: Direction,
+        cx: &mut Context<'_>,
+        mut op: impl FnMut(&S) -> io::Result<R>,
+    ) -> Poll<io::Result<R>> {
+        loop {
+            let observed = match self.io.poll_ready(direction, cx) {
+                Poll::Ready(observed) => observed,
+                Poll::Pending => return Poll::Pending,
+            };
+            match op(&self.source) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    self.io.clear_readiness(observed, direction);
+                },
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
+
+impl<S: Source> Drop for PollEvented<S> {
+    fn drop(&mut self) {
+        self.reactor.deregister(&mut self.source, self.token);
+    }
+}
+
+// 由 reactor 驱动的 TCP 监听器
+pub struct AsyncTcpListener {
+    inner: PollEvented<mio::net::TcpListener>,
+}
+
+impl AsyncTcpListener {
+    // 在运行时 worker 线程上创建时注册到该运行时的 reactor
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        Ok(AsyncTcpListener {
+            inner: PollEvented::new(mio::net::TcpListener::bind(addr)?)?,
+        })
+    }
+
+    pub fn from_std(listener: std::net::TcpListener) -> io::Result<Self> {
+        listener.set_nonblocking(true)?;
+        Ok(AsyncTcpListener {
+            inner: PollEvented::new(mio::net::TcpListener::from_std(listener))?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.source.local_addr()
+    }
+
+    pub async fn accept(&self) -> io::Result<(AsyncTcpStream, SocketAddr)> {
+        let (stream, addr) = poll_fn(|cx| self.inner.poll_io(Direction::Read, cx, |listener| listener.accept())).await?;
+        Ok((AsyncTcpStream::new(stream)?, addr))
+    }
+}
+
+// 由 reactor 驱动的 TCP 连接，实现 futures-lite 的 AsyncRead / AsyncWrite
+pub struct AsyncTcpStream {
+    inner: PollEvented<mio::net::TcpStream>,
+}
+
+impl AsyncTcpStream {
+    fn new(stream: mio::net::TcpStream) -> io::Result<Self> {
+        Ok(AsyncTcpStream {
+            inner: PollEvented::new(stream)?,
+        })
+    }
+
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let stream = AsyncTcpStream::new(mio::net::TcpStream::connect(addr)?)?;
+
+        // 非阻塞 connect：可写之后检查 take_error 和 peer_addr 才能确定连接是否建立
+        poll_fn(|cx| {
+            stream.inner.poll_io(Direction::Write, cx, |stream| {
+                if let Some(err) = stream.take_error()? {
+                    return Err(err);
+                }
+                match stream.peer_addr() {
+                    Ok(_) => Ok(()),
+                    Err(err) if err.kind() == io::ErrorKind::NotConnected => Err(io::ErrorKind::WouldBlock.into()),
+                    Err(err) => Err(err),
+                }
+            })
+        })
+        .await?;
+
+        Ok(stream)
+    }
+
+    pub fn from_std(stream: std::net::TcpStream) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        AsyncTcpStream::new(mio::net::TcpStream::from_std(stream))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.source.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.source.peer_addr()
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.inner.source.shutdown(how)
+    }
+}
+
+impl AsyncRead for AsyncTcpStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.inner.poll_io(Direction::Read, cx, |mut stream| stream.read(buf))
+    }
+}
+
+impl AsyncWrite for AsyncTcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.inner.poll_io(Direction::Write, cx, |mut stream| stream.write(buf))
+    }
+
+    // TcpStream 没有用户态缓冲区，写入的数据已经交给内核，不需要等待可写
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.inner.source.shutdown(Shutdown::Write))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commons::Runtime;
+    use futures_lite::future::poll_once;
+    use futures_lite::{AsyncReadExt, AsyncWriteExt};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn loopback_round_trip() {
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+
+        let echoed = runtime.block_on(async {
+            let listener = AsyncTcpListener::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = handle.spawn(async move {
+                let (mut stream, peer) = listener.accept().await.unwrap();
+                let mut received = Vec::new();
+                stream.read_to_end(&mut received).await.unwrap();
+                stream.write_all(&received).await.unwrap();
+                stream.close().await.unwrap();
+                peer
+            });
+
+            let mut client = AsyncTcpStream::connect(addr).await.unwrap();
+            client.write_all(b"hello").await.unwrap();
+            client.flush().await.unwrap();
+            client.close().await.unwrap();
+            let mut echoed = String::new();
+            client.read_to_string(&mut echoed).await.unwrap();
+
+            assert_eq!(server.await.unwrap(), client.local_addr().unwrap());
+            echoed
+        });
+        assert_eq!(echoed, "hello");
+        runtime.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn readiness_wakes_pending_read() {
+        let mut runtime = Runtime::new();
+        runtime.run();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let writer = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_millis(50));
+            stream.write_all(b"ready").unwrap();
+        });
+
+        let (first_poll_pending, received, waited) = runtime.block_on(async {
+            let mut stream = AsyncTcpStream::connect(addr).await.unwrap();
+            let started = Instant::now();
+            let mut buf = [0; 5];
+            let mut read = stream.read_exact(&mut buf);
+            // 对端还没写入：读被挂起，由 reactor 在可读时唤醒
+            let first_poll_pending = poll_once(&mut read).await.is_none();
+            read.await.unwrap();
+            (first_poll_pending, buf, started.elapsed())
+        });
+
+        assert!(first_poll_pending);
+        assert_eq!(&received, b"ready");
+        assert!(waited >= Duration::from_millis(20));
+        writer.join().unwrap();
+        runtime.shutdown(Duration::from_secs(1));
+    }
+}
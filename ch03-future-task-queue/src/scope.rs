@@ -0,0 +1,268 @@
+//! 结构化并发作用域
+//! scope 中 spawn 的子任务可以借用外部的数据，作用域在所有子任务结束后才完成；
+//! 任一子任务（或作用域主体）返回 Err 时，其余子任务立即被取消，作用域返回这个错误，
+//! 子任务 panic 时同样会丢弃其余子任务并把 panic 传给 await 作用域的任务；
+//! 子任务与作用域主体在同一个任务中并发 poll，不会并行执行，CPU 密集的工作仍应交给 spawn / spawn_blocking
+
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+type Child<'a, E> = Pin<Box<dyn Future<Output = Result<(), E>> + Send + 'a>>;
+
+struct ScopeState<'a, E> {
+    // 新 spawn、尚未被作用域取走的子任务
+    spawned: Vec<Child<'a, E>>,
+    // 作用域所在任务的 waker，在作用域之外 spawn 时用它通知作用域
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+// 作用域句柄，clone 后可以交给子任务继续 spawn
+pub struct Scope<'a, E> {
+    state: Arc<Mutex<ScopeState<'a, E>>>,
+}
+
+impl<E> Clone for Scope<'_, E> {
+    fn clone(&self) -> Self {
+        Scope { state: self.state.clone() }
+    }
+}
+
+impl<'a, E: Send + 'a> Scope<'a, E> {
+    fn new() -> Self {
+        Scope {
+            state: Arc::new(Mutex::new(ScopeState {
+                spawned: Vec::new(),
+                waker: None,
+                closed: false,
+            })),
+        }
+    }
+
+    // 提交子任务，future 可以借用作用域之外生命周期为 'a 的数据；
+    // 作用域已经结束时 panic
+    #[track_caller]
+    pub fn spawn<F, T>(&self, future: F) -> ScopedJoinHandle<T>
+        where F: Future<Output = Result<T, E>> + Send + 'a,
+        T: Send + 'a
+    {
+        let slot = Arc::new(Mutex::new(Slot { output: None, waker: None }));
+        let child_slot = slot.clone();
+        let child: Child<'a, E> = Box::pin(async move {
+            let output = future.await?;
+            let mut slot = child_slot.lock().unwrap();
+            slot.output = Some(output);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+            Ok(())
+        });
+
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            assert!(!state.closed, "spawn on a scope that has already finished");
+            state.spawned.push(child);
+            state.waker.clone()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        ScopedJoinHandle { slot }
+    }
+
+    fn take_spawned(&self, waker: &Waker) -> Vec<Child<'a, E>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.waker.as_ref().is_some_and(|current| current.will_wake(waker)) {
+            state.waker = Some(waker.clone());
+        }
+        std::mem::take(&mut state.spawned)
+    }
+}
+
+// 作用域结束（完成、失败或被丢弃）时关闭：丢弃还没取走的子任务，
+// 子任务可能持有 Scope 的 clone，不清空会形成引用环
+struct Close<'s, 'a, E> {
+    scope: &'s Scope<'a, E>,
+}
+
+impl<E> Drop for Close<'_, '_, E> {
+    fn drop(&mut self) {
+        let spawned = {
+            let mut state = self.scope.state.lock().unwrap();
+            state.closed = true;
+            state.waker = None;
+            std::mem::take(&mut state.spawned)
+        };
+        drop(spawned);
+    }
+}
+
+struct Slot<T> {
+    output: Option<T>,
+    waker: Option<Waker>,
+}
+
+// Scope::spawn 返回的句柄：await 得到子任务的输出；
+// 子任务失败时整个作用域随之结束，等待它的主体也会被丢弃，因此不需要错误分支
+pub struct ScopedJoinHandle<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Future for ScopedJoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+// 创建作用域：
+// let total = scope(|s| async move {
+//     let a = s.spawn(async { Ok::<_, Error>(data.len()) });
+//     Ok(a.await)
+// }).await?;
+// f 的返回值是作用域主体，主体和所有子任务都成功后返回主体的输出
+pub async fn scope<'a, T, E, F, Fut>(f: F) -> Result<T, E>
+    where F: FnOnce(Scope<'a, E>) -> Fut,
+    Fut: Future<Output = Result<T, E>> + 'a,
+    E: Send + 'a
+{
+    let scope = Scope::new();
+    let _close = Close { scope: &scope };
+    let mut body = pin!(f(scope.clone()));
+    let mut output = None;
+    let mut children: Vec<Child<'a, E>> = Vec::new();
+
+    poll_fn(|cx| {
+        if output.is_none() {
+            if let Poll::Ready(result) = body.as_mut().poll(cx) {
+                output = Some(result?);
+            }
+        }
+
+        // 每次唤醒 poll 所有子任务；本轮 poll 中新 spawn 的子任务也立即 poll 一次
+        let mut next = 0;
+        loop {
+            children.extend(scope.take_spawned(cx.waker()));
+            if next == children.len() {
+                break;
+            }
+            while next < children.len() {
+                match children[next].as_mut().poll(cx) {
+                    Poll::Ready(Ok(())) => drop(children.swap_remove(next)),
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => next += 1,
+                }
+            }
+        }
+
+        if children.is_empty() {
+            if let Some(output) = output.take() {
+                return Poll::Ready(Ok(output));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Simulation;
+    use crate::timer;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    // 被丢弃时记录，用来确认子任务被取消而不是执行完
+    struct DropFlag<'a>(&'a AtomicBool);
+
+    impl Drop for DropFlag<'_> {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn children_borrow_and_all_complete() {
+        let words = vec!["async", "rust", "runtime"];
+        let total = Simulation::new(1).block_on(async {
+            let words = &words;
+            scope(|s| async move {
+                let tasks: Vec<_> = words
+                    .iter()
+                    .map(|word| s.spawn(async move {
+                        timer::sleep(Duration::from_millis(10)).await;
+                        Ok::<_, String>(word.len())
+                    }))
+                    .collect();
+                let mut total = 0;
+                for task in tasks {
+                    total += task.await;
+                }
+                Ok(total)
+            })
+            .await
+        });
+        assert_eq!(total, Ok(16));
+    }
+
+    #[test]
+    fn child_error_cancels_siblings() {
+        let (finished, dropped) = (AtomicBool::new(false), AtomicBool::new(false));
+        let simulation = Simulation::new(1);
+        let result: Result<(), &str> = simulation.block_on(async {
+            let (finished, dropped) = (&finished, &dropped);
+            scope(|s| async move {
+                s.spawn(async move {
+                    let _flag = DropFlag(dropped);
+                    timer::sleep(Duration::from_secs(10)).await;
+                    finished.store(true, Ordering::SeqCst);
+                    Ok(())
+                });
+                s.spawn(async {
+                    timer::sleep(Duration::from_millis(10)).await;
+                    Err::<(), _>("boom")
+                });
+                Ok(())
+            })
+            .await
+        });
+
+        assert_eq!(result, Err("boom"));
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(!finished.load(Ordering::SeqCst));
+        // 作用域在出错时立即返回，不等慢的子任务
+        assert_eq!(simulation.elapsed(), Duration::from_millis(10));
+    }
+
+    #[test]
+    fn body_error_cancels_children() {
+        let dropped = AtomicBool::new(false);
+        let result: Result<(), &str> = Simulation::new(1).block_on(async {
+            let dropped = &dropped;
+            scope(|s| async move {
+                s.spawn(async move {
+                    let _flag = DropFlag(dropped);
+                    std::future::pending::<()>().await;
+                    Ok(())
+                });
+                timer::sleep(Duration::from_millis(10)).await;
+                Err("body failed")
+            })
+            .await
+        });
+
+        assert_eq!(result, Err("body failed"));
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+}
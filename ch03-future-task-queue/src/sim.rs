@@ -0,0 +1,326 @@
+//! 确定性模拟运行时
+//! 单线程执行，虚拟时钟只在所有任务都空闲时跳到下一个定时器的截止时间，
+//! 就绪任务的执行顺序由种子决定：同一个种子总是得到同样的交错顺序，可以用来重放竞态；
+//! 只有定时器和任务之间的唤醒是确定的：spawn_blocking 可以使用，block_on 在空闲时等待进行中的阻塞任务完成，
+//! 但它们在真实时间中执行，虚拟时钟不会等待它们；reactor 的 I/O 需要 Runtime，在模拟中不可用
+
+use crate::multi_worker_queue::ParkSignal;
+use crate::task::{spawn_local_task, JoinHandle, TaskRegistry};
+use crate::timer::TimerDriver;
+use async_task::Runnable;
+use std::cell::{Cell, RefCell};
+use std::future::Future;
+use std::panic::{catch_unwind, Location};
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+// xorshift64*：足够用来打乱调度顺序，同一个种子得到同一串随机数
+struct Rng {
+    state: Cell<u64>,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // splitmix64 打散种子，避免相近的种子得到相近的序列，状态不能为 0
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        Rng { state: Cell::new(if z == 0 { 0x2545_F491_4F6C_DD1D } else { z }) }
+    }
+
+    fn next_u64(&self) -> u64 {
+        let mut x = self.state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state.set(x);
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+struct SimInner {
+    seed: u64,
+    rng: Rng,
+    // 虚拟时钟：start 加上已推进的时间
+    start: Instant,
+    elapsed: Cell<Duration>,
+    timer: Arc<TimerDriver>,
+    // 就绪的任务，waker 可能在任意线程上调用，所以用 Mutex
+    ready: Arc<Mutex<Vec<Runnable>>>,
+    registry: Arc<TaskRegistry>,
+    // 在其他线程上进行中的操作数，以及等待它们的 block_on 线程
+    external: Arc<AtomicUsize>,
+    thread: Thread,
+}
+
+impl SimInner {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed.get()
+    }
+
+    // 随机取出一个就绪任务
+    fn pick(&self) -> Option<Runnable> {
+        let mut ready = self.ready.lock().unwrap();
+        if ready.is_empty() {
+            return None;
+        }
+        let index = (self.rng.next_u64() % ready.len() as u64) as usize;
+        Some(ready.swap_remove(index))
+    }
+}
+
+thread_local! {
+    // 正在 block_on 的模拟，供 timer 和 spawn 使用
+    static CURRENT: RefCell<Option<Rc<SimInner>>> = const { RefCell::new(None) };
+}
+
+struct Enter {
+    previous: Option<Rc<SimInner>>,
+}
+
+impl Enter {
+    fn new(inner: &Rc<SimInner>) -> Self {
+        let previous = CURRENT.with(|current| current.borrow_mut().replace(inner.clone()));
+        Enter { previous }
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+pub(crate) fn current_timer() -> Option<Arc<TimerDriver>> {
+    CURRENT.with(|current| current.borrow().as_ref().map(|inner| inner.timer.clone()))
+}
+
+pub(crate) fn now() -> Option<Instant> {
+    CURRENT.with(|current| current.borrow().as_ref().map(|inner| inner.now()))
+}
+
+// 模拟之外的线程上进行中的操作（目前是 spawn_blocking）：释放前 block_on 不会判定为死锁，
+// 必须在操作的结果唤醒等待方之后再释放
+pub(crate) struct ExternalOp {
+    pending: Arc<AtomicUsize>,
+    thread: Thread,
+}
+
+impl Drop for ExternalOp {
+    fn drop(&mut self) {
+        self.pending.fetch_sub(1, Ordering::AcqRel);
+        self.thread.unpark();
+    }
+}
+
+// 不在 Simulation::block_on 中时返回 None
+pub(crate) fn external_op() -> Option<ExternalOp> {
+    CURRENT.with(|current| {
+        current.borrow().as_ref().map(|inner| {
+            inner.external.fetch_add(1, Ordering::AcqRel);
+            ExternalOp { pending: inner.external.clone(), thread: inner.thread.clone() }
+        })
+    })
+}
+
+// 模拟运行时：不是 Send，任务在调用 block_on 的线程上执行
+pub struct Simulation {
+    inner: Rc<SimInner>,
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        Simulation {
+            inner: Rc::new(SimInner {
+                seed,
+                rng: Rng::new(seed),
+                start: Instant::now(),
+                elapsed: Cell::new(Duration::ZERO),
+                timer: TimerDriver::new_virtual(),
+                ready: Arc::new(Mutex::new(Vec::new())),
+                registry: Arc::new(TaskRegistry::new()),
+                external: Arc::new(AtomicUsize::new(0)),
+                // Simulation 不是 Send，block_on 总在创建它的线程上执行
+                thread: thread::current(),
+            }),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.inner.seed
+    }
+
+    // 虚拟时钟的当前时间
+    pub fn now(&self) -> Instant {
+        self.inner.now()
+    }
+
+    // 虚拟时钟已经推进的时间
+    pub fn elapsed(&self) -> Duration {
+        self.inner.elapsed.get()
+    }
+
+    #[track_caller]
+    pub fn spawn<F, T>(&self, future: F) -> JoinHandle<T>
+        where F: Future<Output = T> + 'static,
+        T: 'static
+    {
+        spawn_on(&self.inner, Location::caller(), future)
+    }
+
+    // 运行 future 直到完成；没有就绪任务时把虚拟时钟推进到下一个定时器，
+    // 没有定时器时等待进行中的 spawn_blocking 完成，三者都没有说明发生了死锁，直接 panic 并给出种子
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let inner = &self.inner;
+        let _enter = Enter::new(inner);
+        let signal = ParkSignal::new();
+        let waker = Waker::from(signal.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            if signal.take() {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+
+            if let Some(runnable) = inner.pick() {
+                let _ = catch_unwind(|| runnable.run());
+                continue;
+            }
+            if signal.is_woken() {
+                continue;
+            }
+
+            match inner.timer.next_deadline() {
+                Some(deadline) => {
+                    if deadline > inner.now() {
+                        inner.elapsed.set(deadline - inner.start);
+                    }
+                    inner.timer.fire_expired(inner.now());
+                },
+                // 阻塞任务完成时先唤醒等待方再释放 ExternalOp，醒来后一定能看到就绪的任务
+                None if inner.external.load(Ordering::Acquire) > 0 => thread::park(),
+                None => panic!(
+                    "simulation with seed {} deadlocked: no runnable task, no pending timer and no running \
+                     spawn_blocking job (reactor I/O is not supported in a simulation)",
+                    inner.seed
+                ),
+            }
+        }
+    }
+}
+
+impl Drop for Simulation {
+    // 与 LocalExecutor 一样，在本线程唤醒并丢弃所有剩余任务
+    fn drop(&mut self) {
+        self.inner.registry.wake_all();
+        let ready = std::mem::take(&mut *self.inner.ready.lock().unwrap());
+        drop(ready);
+    }
+}
+
+// 在当前模拟中提交任务，不在 Simulation::block_on 中时 panic
+#[track_caller]
+pub fn spawn<F, T>(future: F) -> JoinHandle<T>
+    where F: Future<Output = T> + 'static,
+    T: 'static
+{
+    let location = Location::caller();
+    let inner = CURRENT
+        .with(|current| current.borrow().clone())
+        .expect("sim::spawn must be called inside Simulation::block_on");
+    spawn_on(&inner, location, future)
+}
+
+fn spawn_on<F, T>(inner: &Rc<SimInner>, location: &'static Location<'static>, future: F) -> JoinHandle<T>
+    where F: Future<Output = T> + 'static,
+    T: 'static
+{
+    let ready = inner.ready.clone();
+    spawn_local_task(&inner.registry, location, future, move |runnable| ready.lock().unwrap().push(runnable))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking;
+    use crate::timer;
+    use futures_lite::future;
+
+    // 多个任务交替 yield，记录执行顺序
+    fn interleaving(seed: u64) -> Vec<usize> {
+        let simulation = Simulation::new(seed);
+        let order = Rc::new(RefCell::new(Vec::new()));
+        simulation.block_on(async {
+            let tasks: Vec<_> = (0..4)
+                .map(|id| {
+                    let order = order.clone();
+                    spawn(async move {
+                        for _ in 0..3 {
+                            order.borrow_mut().push(id);
+                            future::yield_now().await;
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        let order = order.borrow().clone();
+        order
+    }
+
+    #[test]
+    fn same_seed_replays_same_interleaving() {
+        for seed in 0..8 {
+            assert_eq!(interleaving(seed), interleaving(seed));
+        }
+    }
+
+    #[test]
+    fn different_seeds_change_interleaving() {
+        let first = interleaving(0);
+        assert!((1..16).any(|seed| interleaving(seed) != first));
+    }
+
+    #[test]
+    fn virtual_clock_jumps_to_next_timer() {
+        let simulation = Simulation::new(1);
+        let started = Instant::now();
+        let virtual_start = simulation.now();
+
+        simulation.block_on(async {
+            timer::sleep(Duration::from_secs(3600)).await;
+            timer::sleep(Duration::from_secs(60)).await;
+        });
+
+        assert_eq!(simulation.elapsed(), Duration::from_secs(3660));
+        assert_eq!(simulation.now() - virtual_start, Duration::from_secs(3660));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn block_on_waits_for_spawn_blocking() {
+        let simulation = Simulation::new(1);
+        let output = simulation.block_on(async {
+            let task = spawn(async { blocking::spawn_blocking(|| 7).await.unwrap() });
+            task.await.unwrap() + blocking::spawn_blocking(|| 1).await.unwrap()
+        });
+        assert_eq!(output, 8);
+    }
+
+    #[test]
+    #[should_panic(expected = "simulation with seed 3 deadlocked")]
+    fn deadlock_panics_with_seed() {
+        Simulation::new(3).block_on(future::pending::<()>());
+    }
+}
@@ -1,15 +1,14 @@
+//! 一个 Queue -> 多个线程处理
+//! 单个 Queue
+
 use std::{future::Future, panic::catch_unwind, thread};
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use std::sync::LazyLock;
 
 use async_task::{Runnable, Task};
 
-///! 一个 Queue -> 多个线程处理
-///! 单个 Queue
-
-
 pub struct CounterFuture {
     pub count: u32,
 }
@@ -0,0 +1,748 @@
+//! 异步同步原语
+//! 不依赖 tokio，也不依赖具体运行时：拿不到锁或许可的 future 把 waker 登记到先进先出的等待队列，
+//! 释放时按排队顺序唤醒，不再用 try_lock 失败后 wake_by_ref 空转；
+//! Mutex、RwLock 都建立在公平的 Semaphore 之上，后来的请求不会插队到已经排队的请求前面
+
+use std::cell::UnsafeCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+struct Waiter {
+    needed: usize,
+    waker: Option<Waker>,
+    // release 时已经把许可分配给了这个等待者
+    granted: bool,
+}
+
+struct SemaphoreState {
+    permits: usize,
+    // 等待者按到达顺序排队，只有队首满足时才继续向后分配
+    queue: VecDeque<usize>,
+    waiters: HashMap<usize, Waiter>,
+    next_id: usize,
+}
+
+impl SemaphoreState {
+    // 从队首开始分配许可，返回需要唤醒的 waker，由调用方在锁外唤醒
+    fn grant(&mut self) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        while let Some(&id) = self.queue.front() {
+            let waiter = self.waiters.get_mut(&id).unwrap();
+            if waiter.needed > self.permits {
+                break;
+            }
+            self.permits -= waiter.needed;
+            waiter.granted = true;
+            wakers.extend(waiter.waker.take());
+            self.queue.pop_front();
+        }
+        wakers
+    }
+}
+
+// 公平信号量：acquire 按调用顺序获得许可
+pub struct Semaphore {
+    state: std::sync::Mutex<SemaphoreState>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            state: std::sync::Mutex::new(SemaphoreState {
+                permits,
+                queue: VecDeque::new(),
+                waiters: HashMap::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+
+    pub fn add_permits(&self, permits: usize) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.permits += permits;
+            state.grant()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn acquire(&self) -> Acquire<'_> {
+        self.acquire_many(1)
+    }
+
+    // 一次获取多个许可；超过信号量总许可数的请求永远不会完成，并会挡住排在后面的请求
+    pub fn acquire_many(&self, permits: usize) -> Acquire<'_> {
+        Acquire { semaphore: self, needed: permits, id: None }
+    }
+
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    // 已经有人排队时也返回 None，保持公平
+    pub fn try_acquire_many(&self, permits: usize) -> Option<SemaphorePermit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if state.queue.is_empty() && state.permits >= permits {
+            state.permits -= permits;
+            Some(SemaphorePermit { semaphore: self, permits })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Semaphore").field("permits", &self.available_permits()).finish()
+    }
+}
+
+// acquire / acquire_many 返回的 future；在获得许可前被丢弃会退出队列，不影响后面的等待者
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    needed: usize,
+    id: Option<usize>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let mut state = this.semaphore.state.lock().unwrap();
+
+        match this.id {
+            None if state.queue.is_empty() && state.permits >= this.needed => {
+                state.permits -= this.needed;
+            },
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.insert(id, Waiter { needed: this.needed, waker: Some(cx.waker().clone()), granted: false });
+                state.queue.push_back(id);
+                this.id = Some(id);
+                return Poll::Pending;
+            },
+            Some(id) => {
+                let waiter = state.waiters.get_mut(&id).unwrap();
+                if !waiter.granted {
+                    waiter.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                state.waiters.remove(&id);
+                this.id = None;
+            },
+        }
+        Poll::Ready(SemaphorePermit { semaphore: this.semaphore, permits: this.needed })
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else { return };
+        let wakers = {
+            let mut state = self.semaphore.state.lock().unwrap();
+            let waiter = state.waiters.remove(&id).unwrap();
+            if waiter.granted {
+                // 许可已经分配但没有被取走，归还给后面的等待者
+                state.permits += waiter.needed;
+            } else {
+                state.queue.retain(|&queued| queued != id);
+            }
+            // 队首离开后，后面的等待者可能已经可以满足
+            state.grant()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+// 持有的许可，drop 时归还
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    // 不归还许可，相当于永久减少信号量的许可数
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+// 异步互斥锁：guard 可以跨 await 持有，等待者按先后顺序获得锁
+pub struct Mutex<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: 对 value 的访问由只有一个许可的信号量保证互斥，与 std::sync::Mutex 的约束相同
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Self {
+        Mutex { semaphore: Semaphore::new(1), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        let permit = self.semaphore.acquire().await;
+        MutexGuard { mutex: self, _permit: permit }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire()?;
+        Some(MutexGuard { mutex: self, _permit: permit })
+    }
+
+    // 独占借用时不需要加锁
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex").finish_non_exhaustive()
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+// SAFETY: guard 只提供 &T / &mut T，跨线程共享 guard 等价于共享 &T
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: 持有唯一的许可，期间没有其他 guard
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: 同上
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+// 写锁占用全部许可，读锁各占一个
+const MAX_READERS: usize = usize::MAX >> 3;
+
+// 异步读写锁：公平排队，排队中的写者会挡住之后到达的读者，写者不会被持续的读者饿死
+pub struct RwLock<T: ?Sized> {
+    semaphore: Semaphore,
+    value: UnsafeCell<T>,
+}
+
+// SAFETY: 多个读者可以同时得到 &T，因此 Sync 还要求 T: Sync
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub fn new(value: T) -> Self {
+        RwLock { semaphore: Semaphore::new(MAX_READERS), value: UnsafeCell::new(value) }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub async fn read(&self) -> RwLockReadGuard<'_, T> {
+        let permit = self.semaphore.acquire().await;
+        RwLockReadGuard { lock: self, _permit: permit }
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let permit = self.semaphore.acquire_many(MAX_READERS).await;
+        RwLockWriteGuard { lock: self, _permit: permit }
+    }
+
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire()?;
+        Some(RwLockReadGuard { lock: self, _permit: permit })
+    }
+
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        let permit = self.semaphore.try_acquire_many(MAX_READERS)?;
+        Some(RwLockWriteGuard { lock: self, _permit: permit })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RwLock").finish_non_exhaustive()
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: 持有读许可时没有写者
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    _permit: SemaphorePermit<'a>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: 持有全部许可，期间没有其他读者或写者
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: 同上
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+struct NotifyWaiter {
+    waker: Option<Waker>,
+    notified: bool,
+}
+
+struct NotifyState {
+    // notify_one 时没有等待者，保存一次通知给下一个 notified()
+    permit: bool,
+    // 每次 notify_waiters 加一，创建时记录的值变化说明已被广播唤醒
+    generation: usize,
+    queue: VecDeque<usize>,
+    waiters: HashMap<usize, NotifyWaiter>,
+    next_id: usize,
+}
+
+impl NotifyState {
+    // 把一次通知交给队首的等待者，没有等待者时保存下来
+    fn notify_one(&mut self) -> Option<Waker> {
+        match self.queue.pop_front() {
+            Some(id) => {
+                let waiter = self.waiters.get_mut(&id).unwrap();
+                waiter.notified = true;
+                waiter.waker.take()
+            },
+            None => {
+                self.permit = true;
+                None
+            },
+        }
+    }
+}
+
+// 任务间的通知：notify_one 按等待顺序唤醒一个，notify_waiters 唤醒当前所有等待者
+pub struct Notify {
+    state: std::sync::Mutex<NotifyState>,
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notify {
+    pub fn new() -> Self {
+        Notify {
+            state: std::sync::Mutex::new(NotifyState {
+                permit: false,
+                generation: 0,
+                queue: VecDeque::new(),
+                waiters: HashMap::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    // 在 notify_waiters 之前创建的 Notified 都会被唤醒，即使还没被 poll 过
+    pub fn notified(&self) -> Notified<'_> {
+        let generation = self.state.lock().unwrap().generation;
+        Notified { notify: self, generation, id: None }
+    }
+
+    pub fn notify_one(&self) {
+        let waker = self.state.lock().unwrap().notify_one();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    // 不保存通知：之后创建的 Notified 不受影响
+    pub fn notify_waiters(&self) {
+        let wakers: Vec<Waker> = {
+            let mut state = self.state.lock().unwrap();
+            state.generation += 1;
+            state.queue.clear();
+            state.waiters.values_mut().filter_map(|waiter| waiter.waker.take()).collect()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl fmt::Debug for Notify {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Notify").finish_non_exhaustive()
+    }
+}
+
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    generation: usize,
+    id: Option<usize>,
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let mut state = this.notify.state.lock().unwrap();
+
+        if state.generation != this.generation {
+            if let Some(id) = this.id.take() {
+                state.waiters.remove(&id);
+            }
+            return Poll::Ready(());
+        }
+
+        match this.id {
+            None if state.permit => {
+                state.permit = false;
+                Poll::Ready(())
+            },
+            None => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.waiters.insert(id, NotifyWaiter { waker: Some(cx.waker().clone()), notified: false });
+                state.queue.push_back(id);
+                this.id = Some(id);
+                Poll::Pending
+            },
+            Some(id) => {
+                let waiter = state.waiters.get_mut(&id).unwrap();
+                if waiter.notified {
+                    state.waiters.remove(&id);
+                    this.id = None;
+                    return Poll::Ready(());
+                }
+                waiter.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else { return };
+        let waker = {
+            let mut state = self.notify.state.lock().unwrap();
+            let waiter = state.waiters.remove(&id).unwrap();
+            if waiter.notified && state.generation == self.generation {
+                // 收到了 notify_one 却没有被消费，转交给下一个等待者，通知不会丢失
+                state.notify_one()
+            } else {
+                state.queue.retain(|&queued| queued != id);
+                None
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+struct BarrierState {
+    arrived: usize,
+    generation: usize,
+    wakers: HashMap<usize, Waker>,
+    next_id: usize,
+}
+
+// 屏障：凑齐 n 个 wait 后一起放行，之后可以重复使用
+pub struct Barrier {
+    parties: usize,
+    state: std::sync::Mutex<BarrierState>,
+}
+
+// wait 的结果，每一轮恰好有一个 leader
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    pub fn new(parties: usize) -> Self {
+        Barrier {
+            parties: parties.max(1),
+            state: std::sync::Mutex::new(BarrierState {
+                arrived: 0,
+                generation: 0,
+                wakers: HashMap::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    // 首次 poll 时计入到达；到达后被取消仍然计数，与 std::sync::Barrier 一样不要在 select 中使用
+    pub async fn wait(&self) -> BarrierWaitResult {
+        let mut arrival: Option<(usize, usize)> = None;
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            match arrival {
+                None => {
+                    state.arrived += 1;
+                    if state.arrived == self.parties {
+                        // 最后一个到达者放行本轮所有等待者
+                        state.arrived = 0;
+                        state.generation += 1;
+                        let wakers: Vec<Waker> = state.wakers.drain().map(|(_, waker)| waker).collect();
+                        drop(state);
+                        for waker in wakers {
+                            waker.wake();
+                        }
+                        return Poll::Ready(BarrierWaitResult(true));
+                    }
+                    let id = state.next_id;
+                    state.next_id += 1;
+                    state.wakers.insert(id, cx.waker().clone());
+                    arrival = Some((id, state.generation));
+                    Poll::Pending
+                },
+                Some((_, generation)) if generation != state.generation => Poll::Ready(BarrierWaitResult(false)),
+                Some((id, _)) => {
+                    state.wakers.insert(id, cx.waker().clone());
+                    Poll::Pending
+                },
+            }
+        })
+        .await
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Barrier").field("parties", &self.parties).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{self, Simulation};
+    use futures_lite::future::{block_on, poll_once, yield_now};
+    use std::pin::pin;
+    use std::rc::Rc;
+
+    #[test]
+    fn semaphore_grants_in_arrival_order() {
+        let semaphore = Semaphore::new(2);
+        let held = semaphore.try_acquire_many(2).unwrap();
+        assert!(semaphore.try_acquire().is_none());
+
+        let mut many = pin!(semaphore.acquire_many(2));
+        let mut one = pin!(semaphore.acquire());
+        assert!(block_on(poll_once(many.as_mut())).is_none());
+        assert!(block_on(poll_once(one.as_mut())).is_none());
+
+        // 归还一个许可不够队首，后面只要一个许可的请求也不能插队
+        drop(held);
+        let permit = block_on(poll_once(many.as_mut())).unwrap();
+        assert!(block_on(poll_once(one.as_mut())).is_none());
+        drop(permit);
+        assert!(block_on(poll_once(one.as_mut())).is_some());
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn dropped_acquire_leaves_queue() {
+        let semaphore = Semaphore::new(1);
+        let held = semaphore.try_acquire().unwrap();
+        {
+            let mut first = pin!(semaphore.acquire());
+            assert!(block_on(poll_once(first.as_mut())).is_none());
+        }
+        let mut second = pin!(semaphore.acquire());
+        assert!(block_on(poll_once(second.as_mut())).is_none());
+        drop(held);
+        assert!(block_on(poll_once(second.as_mut())).is_some());
+    }
+
+    #[test]
+    fn forgotten_permit_is_not_returned() {
+        let semaphore = Semaphore::new(3);
+        semaphore.try_acquire().unwrap().forget();
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn mutex_guard_held_across_await() {
+        let mutex = Rc::new(Mutex::new(0));
+        Simulation::new(7).block_on(async {
+            let tasks: Vec<_> = (0..4)
+                .map(|_| {
+                    let mutex = mutex.clone();
+                    sim::spawn(async move {
+                        for _ in 0..10 {
+                            let mut guard = mutex.lock().await;
+                            let value = *guard;
+                            yield_now().await;
+                            *guard = value + 1;
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        });
+        assert_eq!(*block_on(mutex.lock()), 40);
+    }
+
+    #[test]
+    fn rwlock_readers_share_and_writer_is_not_starved() {
+        let lock = RwLock::new(1);
+        let first = lock.try_read().unwrap();
+        let second = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+
+        let mut write = pin!(lock.write());
+        assert!(block_on(poll_once(write.as_mut())).is_none());
+        // 排队中的写者挡住后来的读者
+        let mut read = pin!(lock.read());
+        assert!(block_on(poll_once(read.as_mut())).is_none());
+
+        drop((first, second));
+        let mut guard = block_on(poll_once(write.as_mut())).unwrap();
+        *guard = 2;
+        drop(guard);
+        assert_eq!(*block_on(poll_once(read.as_mut())).unwrap(), 2);
+    }
+
+    #[test]
+    fn notify_one_stores_a_permit() {
+        let notify = Notify::new();
+        notify.notify_one();
+        let mut notified = pin!(notify.notified());
+        assert!(block_on(poll_once(notified.as_mut())).is_some());
+        let mut again = pin!(notify.notified());
+        assert!(block_on(poll_once(again.as_mut())).is_none());
+    }
+
+    #[test]
+    fn notify_waiters_wakes_current_waiters_only() {
+        let notify = Notify::new();
+        let mut first = pin!(notify.notified());
+        let mut second = pin!(notify.notified());
+        assert!(block_on(poll_once(first.as_mut())).is_none());
+
+        // 尚未 poll 的 Notified 也算作当前等待者
+        notify.notify_waiters();
+        assert!(block_on(poll_once(first.as_mut())).is_some());
+        assert!(block_on(poll_once(second.as_mut())).is_some());
+        let mut later = pin!(notify.notified());
+        assert!(block_on(poll_once(later.as_mut())).is_none());
+    }
+
+    #[test]
+    fn unconsumed_notification_passes_to_next_waiter() {
+        let notify = Notify::new();
+        let mut second = pin!(notify.notified());
+        {
+            let mut first = pin!(notify.notified());
+            assert!(block_on(poll_once(first.as_mut())).is_none());
+            assert!(block_on(poll_once(second.as_mut())).is_none());
+            notify.notify_one();
+        }
+        assert!(block_on(poll_once(second.as_mut())).is_some());
+    }
+
+    #[test]
+    fn barrier_releases_with_one_leader() {
+        let barrier = Rc::new(Barrier::new(3));
+        let leaders = Simulation::new(3).block_on(async {
+            let tasks: Vec<_> = (0..3)
+                .map(|_| {
+                    let barrier = barrier.clone();
+                    sim::spawn(async move { barrier.wait().await.is_leader() })
+                })
+                .collect();
+            let mut leaders = 0;
+            for task in tasks {
+                leaders += task.await.unwrap() as usize;
+            }
+            leaders
+        });
+        assert_eq!(leaders, 1);
+    }
+}
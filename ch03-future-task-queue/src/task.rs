@@ -0,0 +1,609 @@
+//! 任务句柄与运行时内部的任务登记
+//! 每个 spawn 出来的任务分配一个进程内唯一的 id，任务结束（完成或被取消）时注销，
+//! 用于关闭运行时时等待任务排空、报告仍未完成的任务，以及导出存活任务的状态
+
+use crate::commons::FutureType;
+use crate::task_local;
+use async_task::{FallibleTask, Runnable, Task};
+use futures_lite::FutureExt;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::panic::{AssertUnwindSafe, Location};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+enum Repr {
+    Cancelled,
+    // panic 负载本身不是 Sync，用 Mutex 包一层，使 JoinError 可以转换成 Box<dyn Error + Send + Sync>
+    Panic(Mutex<Box<dyn Any + Send + 'static>>),
+}
+
+// 任务没有正常完成的原因：被取消，或者 poll 时发生 panic
+pub struct JoinError {
+    repr: Repr,
+}
+
+impl JoinError {
+    pub(crate) fn cancelled() -> Self {
+        JoinError { repr: Repr::Cancelled }
+    }
+
+    pub(crate) fn panic(payload: Box<dyn Any + Send + 'static>) -> Self {
+        JoinError { repr: Repr::Panic(Mutex::new(payload)) }
+    }
+
+    // 任务 future 的输出：外层是 catch_unwind 的结果，内层 None 表示被 abort
+    fn from_output<T>(output: thread::Result<Option<T>>) -> Result<T, JoinError> {
+        match output {
+            Ok(Some(output)) => Ok(output),
+            Ok(None) => Err(JoinError::cancelled()),
+            Err(payload) => Err(JoinError::panic(payload)),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.repr, Repr::Cancelled)
+    }
+
+    pub fn is_panic(&self) -> bool {
+        matches!(self.repr, Repr::Panic(_))
+    }
+
+    // 取出 panic 负载，可以配合 std::panic::resume_unwind 在当前线程重新抛出
+    pub fn try_into_panic(self) -> Result<Box<dyn Any + Send + 'static>, JoinError> {
+        match self.repr {
+            Repr::Panic(payload) => Ok(payload.into_inner().unwrap()),
+            repr => Err(JoinError { repr }),
+        }
+    }
+
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.try_into_panic().expect("`JoinError` reason is not a panic")
+    }
+
+    // panic 负载通常是 &str 或 String
+    fn panic_message(&self) -> Option<String> {
+        match &self.repr {
+            Repr::Panic(payload) => {
+                let payload = payload.lock().unwrap();
+                payload.downcast_ref::<&str>().map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+            },
+            Repr::Cancelled => None,
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.repr, self.panic_message()) {
+            (Repr::Cancelled, _) => write!(f, "task was cancelled"),
+            (Repr::Panic(_), Some(message)) => write!(f, "task panicked with message {:?}", message),
+            (Repr::Panic(_), None) => write!(f, "task panicked"),
+        }
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.repr, self.panic_message()) {
+            (Repr::Cancelled, _) => write!(f, "JoinError::Cancelled"),
+            (Repr::Panic(_), Some(message)) => write!(f, "JoinError::Panic({:?})", message),
+            (Repr::Panic(_), None) => write!(f, "JoinError::Panic(..)"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+// try_spawn / spawn_async 失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    // 有界队列已满
+    Full,
+    // 运行时正在关闭
+    Shutdown,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::Full => write!(f, "runtime queue is full"),
+            SpawnError::Shutdown => write!(f, "runtime is shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for SpawnError {}
+
+pub(crate) struct AbortState {
+    aborted: AtomicBool,
+    // 任务首次 poll 时记录的 waker，abort 时用它唤醒任务
+    waker: OnceLock<Waker>,
+}
+
+impl AbortState {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(AbortState {
+            aborted: AtomicBool::new(false),
+            waker: OnceLock::new(),
+        })
+    }
+
+    fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        if let Some(waker) = self.waker.get() {
+            waker.wake_by_ref();
+        }
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+}
+
+// 可以跨线程取消任务的句柄，不影响 JoinHandle 的 await
+#[derive(Clone)]
+pub struct AbortHandle {
+    state: Arc<AbortState>,
+}
+
+impl AbortHandle {
+    // 任务下一次被调度时丢弃其 future，JoinHandle 得到 JoinError::Cancelled；
+    // 正在执行的 poll（或阻塞闭包）不会被打断
+    pub fn abort(&self) {
+        self.state.abort();
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.state.is_aborted()
+    }
+}
+
+// 包装 future：每次 poll 前检查是否已被 abort，被 abort 时返回 None
+pub(crate) fn abortable<F: Future>(future: F) -> (impl Future<Output = Option<F::Output>>, Arc<AbortState>) {
+    let state = AbortState::new();
+    let task_state = state.clone();
+
+    let future = async move {
+        let mut future = std::pin::pin!(future);
+        std::future::poll_fn(|cx| {
+            // 先记录 waker 再检查标志，与 abort 的"先设标志再取 waker"配合，不会漏掉唤醒
+            let _ = task_state.waker.set(cx.waker().clone());
+            if task_state.is_aborted() {
+                return Poll::Ready(None);
+            }
+            future.as_mut().poll(cx).map(Some)
+        })
+        .await
+    };
+    (future, state)
+}
+
+// LocalExecutor 与 Simulation 共用的单线程任务创建：与多线程运行时一致，继承任务局部变量，
+// 支持 abort，panic 作为 JoinError 返回，任务结束时从登记表注销；任务只能在当前线程上运行和析构
+pub(crate) fn spawn_local_task<F, T, S>(
+    registry: &Arc<TaskRegistry>,
+    location: &'static Location<'static>,
+    future: F,
+    schedule: S,
+) -> JoinHandle<T>
+    where F: Future<Output = T> + 'static,
+    T: 'static,
+    S: Fn(Runnable) + Send + Sync + 'static
+{
+    let future = task_local::inherit(future);
+    let (future, abort) = abortable(future);
+    let future = AssertUnwindSafe(future).catch_unwind();
+
+    let guard = registry.register(location, None, None);
+    let id = guard.id();
+    let future = async move {
+        let guard = guard;
+        guard.run(future).await
+    };
+
+    let (runnable, task) = async_task::spawn_local(future, schedule);
+    runnable.schedule();
+    JoinHandle::new(task, abort, id)
+}
+
+// spawn 返回的任务句柄：await 得到 Result<T, JoinError>
+// future 在 catch_unwind 中运行，panic 被捕获为 JoinError 而不是让等待方永远挂起
+pub struct JoinHandle<T> {
+    task: FallibleTask<thread::Result<Option<T>>>,
+    abort: Arc<AbortState>,
+    id: usize,
+}
+
+impl<T> JoinHandle<T> {
+    pub(crate) fn new(task: Task<thread::Result<Option<T>>>, abort: Arc<AbortState>, id: usize) -> Self {
+        JoinHandle { task: task.fallible(), abort, id }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle { state: self.abort.clone() }
+    }
+
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    // 让任务在后台继续运行，不再关心结果
+    pub fn detach(self) {
+        self.task.detach();
+    }
+
+    // 取消任务并等待取消完成；任务已经完成时返回其结果
+    pub async fn cancel(self) -> Option<Result<T, JoinError>> {
+        self.task.cancel().await.map(JoinError::from_output)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.task).poll(cx) {
+            Poll::Ready(Some(output)) => Poll::Ready(JoinError::from_output(output)),
+            // Runnable 被丢弃（运行时关闭或任务被取消）
+            Poll::Ready(None) => Poll::Ready(Err(JoinError::cancelled())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+// 关闭时仍未完成的任务：id、名称与 spawn 调用位置
+#[derive(Debug, Clone)]
+pub struct PendingTask {
+    pub id: usize,
+    pub name: Option<String>,
+    pub location: &'static Location<'static>,
+}
+
+// Runtime::shutdown / shutdown_now 的结果
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub pending: Vec<PendingTask>,
+    // 被取消或仍在执行的 spawn_blocking 任务数：它们不登记在任务表中，没有名称和调用位置；
+    // 正在执行的阻塞调用无法中断，只能等它自己结束
+    pub blocking: usize,
+}
+
+static NEXT_TASK_ID: AtomicUsize = AtomicUsize::new(1);
+
+// 进程内唯一的任务 id：不同运行时、LocalExecutor、模拟运行时和 spawn_blocking 的任务不会重复
+pub(crate) fn next_task_id() -> usize {
+    NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    // 在队列中等待执行
+    Scheduled,
+    // 正在被 poll
+    Running,
+    // 返回 Pending 后等待被唤醒
+    Idle,
+}
+
+impl TaskState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            0 => TaskState::Scheduled,
+            1 => TaskState::Running,
+            _ => TaskState::Idle,
+        }
+    }
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            TaskState::Scheduled => "scheduled",
+            TaskState::Running => "running",
+            TaskState::Idle => "idle",
+        };
+        f.pad(state)
+    }
+}
+
+// 存活任务的元数据，由登记表、调度闭包和任务 future 共享
+pub(crate) struct TaskInfo {
+    id: usize,
+    name: Option<String>,
+    // 只有多线程运行时的任务有优先级
+    priority: Option<FutureType>,
+    location: &'static Location<'static>,
+    spawned: Instant,
+    // TaskState 的 u8 表示
+    state: AtomicU8,
+    polls: AtomicU64,
+}
+
+impl TaskInfo {
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub(crate) fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub(crate) fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    // 调度闭包把 Runnable 放入队列时调用
+    pub(crate) fn scheduled(&self) {
+        self.state.store(TaskState::Scheduled as u8, Ordering::Release);
+    }
+
+    fn snapshot(&self, now: Instant) -> TaskSnapshot {
+        TaskSnapshot {
+            id: self.id,
+            name: self.name.clone(),
+            priority: self.priority,
+            state: TaskState::from_u8(self.state.load(Ordering::Acquire)),
+            polls: self.polls.load(Ordering::Relaxed),
+            age: now.saturating_duration_since(self.spawned),
+            location: self.location,
+        }
+    }
+}
+
+// Runtime::dump() 中的一个存活任务
+#[derive(Debug, Clone)]
+pub struct TaskSnapshot {
+    pub id: usize,
+    pub name: Option<String>,
+    pub priority: Option<FutureType>,
+    pub state: TaskState,
+    pub polls: u64,
+    // 自 spawn 以来经过的时间
+    pub age: Duration,
+    pub location: &'static Location<'static>,
+}
+
+// Runtime::dump() 的结果，Display 输出为每行一个任务的文本表格
+#[derive(Debug, Clone, Default)]
+pub struct TaskDump {
+    pub tasks: Vec<TaskSnapshot>,
+}
+
+impl fmt::Display for TaskDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} live tasks", self.tasks.len())?;
+        writeln!(f, "{:>6}  {:<20}  {:<10}  {:<9}  {:>7}  {:>12}  spawned at", "id", "name", "priority", "state", "polls", "age")?;
+        for task in &self.tasks {
+            let priority = task.priority.map(|priority| format!("{:?}", priority));
+            writeln!(
+                f,
+                "{:>6}  {:<20}  {:<10}  {:<9}  {:>7}  {:>12}  {}",
+                task.id,
+                task.name.as_deref().unwrap_or("-"),
+                priority.as_deref().unwrap_or("-"),
+                task.state,
+                task.polls,
+                format!("{:.3?}", task.age),
+                task.location
+            )?;
+        }
+        Ok(())
+    }
+}
+
+struct TaskEntry {
+    info: Arc<TaskInfo>,
+    // 任务首次 poll 时记录的 waker，关闭时用它把空闲任务重新调度，从而被取消
+    waker: Option<Waker>,
+}
+
+pub(crate) struct TaskRegistry {
+    live: Mutex<HashMap<usize, TaskEntry>>,
+    // 最后一个任务注销时通知等待排空的 shutdown
+    drained: Condvar,
+}
+
+impl TaskRegistry {
+    pub(crate) fn new() -> Self {
+        TaskRegistry {
+            live: Mutex::new(HashMap::new()),
+            drained: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn register(
+        self: &Arc<Self>,
+        location: &'static Location<'static>,
+        name: Option<String>,
+        priority: Option<FutureType>,
+    ) -> TaskGuard {
+        let info = Arc::new(TaskInfo {
+            id: next_task_id(),
+            name,
+            priority,
+            location,
+            spawned: Instant::now(),
+            state: AtomicU8::new(TaskState::Scheduled as u8),
+            polls: AtomicU64::new(0),
+        });
+        self.live.lock().unwrap().insert(info.id, TaskEntry { info: info.clone(), waker: None });
+        TaskGuard { info, registry: self.clone() }
+    }
+
+    fn bind_waker(&self, id: usize, waker: &Waker) {
+        if let Some(entry) = self.live.lock().unwrap().get_mut(&id) {
+            entry.waker = Some(waker.clone());
+        }
+    }
+
+    // 唤醒所有仍存活的任务：运行时已停止时，重新调度会丢弃 Runnable，
+    // 等待在定时器、I/O 或其他 future 上的空闲任务也能被取消，JoinHandle 不会永远挂起
+    pub(crate) fn wake_all(&self) {
+        let wakers: Vec<Waker> = self.live.lock().unwrap()
+            .values_mut()
+            .filter_map(|entry| entry.waker.take())
+            .collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    fn unregister(&self, id: usize) {
+        let mut live = self.live.lock().unwrap();
+        live.remove(&id);
+        if live.is_empty() {
+            self.drained.notify_all();
+        }
+    }
+
+    // 等待所有任务结束，超时返回 false
+    pub(crate) fn wait_drained(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut live = self.live.lock().unwrap();
+        while !live.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            live = self.drained.wait_timeout(live, deadline - now).unwrap().0;
+        }
+        true
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.live.lock().unwrap().len()
+    }
+
+    pub(crate) fn pending(&self) -> Vec<PendingTask> {
+        let mut pending: Vec<PendingTask> = self.live.lock().unwrap()
+            .values()
+            .map(|entry| PendingTask { id: entry.info.id, name: entry.info.name.clone(), location: entry.info.location })
+            .collect();
+        pending.sort_by_key(|task| task.id);
+        pending
+    }
+
+    // 所有存活任务的快照，按 id 排序
+    pub(crate) fn dump(&self) -> TaskDump {
+        let now = Instant::now();
+        let mut tasks: Vec<TaskSnapshot> = self.live.lock().unwrap()
+            .values()
+            .map(|entry| entry.info.snapshot(now))
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+        TaskDump { tasks }
+    }
+}
+
+// 随 future 一起移动进任务，future 完成或被丢弃时自动注销
+pub(crate) struct TaskGuard {
+    info: Arc<TaskInfo>,
+    registry: Arc<TaskRegistry>,
+}
+
+impl TaskGuard {
+    pub(crate) fn id(&self) -> usize {
+        self.info.id
+    }
+
+    pub(crate) fn info(&self) -> &Arc<TaskInfo> {
+        &self.info
+    }
+
+    // 任务 future 的最外层：第一次 poll 时记录任务自己的 waker（async-task 的 waker 在任务生命周期内不变），
+    // 每次 poll 前后更新任务状态与 poll 次数
+    pub(crate) async fn run<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut bound = false;
+        poll_fn(|cx| {
+            if !bound {
+                self.registry.bind_waker(self.info.id, cx.waker());
+                bound = true;
+            }
+            self.info.state.store(TaskState::Running as u8, Ordering::Release);
+            self.info.polls.fetch_add(1, Ordering::Relaxed);
+            let poll = future.as_mut().poll(cx);
+            // poll 期间被唤醒时状态已经是 Scheduled，不能覆盖成 Idle
+            let _ = self.info.state.compare_exchange(
+                TaskState::Running as u8,
+                TaskState::Idle as u8,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            );
+            poll
+        })
+        .await
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        self.registry.unregister(self.info.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commons::Runtime;
+
+    #[test]
+    fn panicking_task_returns_panic_payload() {
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+
+        let error = runtime.block_on(handle.spawn(async { panic!("boom") })).unwrap_err();
+        assert!(error.is_panic());
+        assert!(!error.is_cancelled());
+        assert_eq!(error.to_string(), "task panicked with message \"boom\"");
+        assert_eq!(*error.into_panic().downcast::<&str>().unwrap(), "boom");
+
+        let code = 7;
+        let error = runtime.block_on(handle.spawn(async move { panic!("exit code {}", code) })).unwrap_err();
+        assert_eq!(*error.into_panic().downcast::<String>().unwrap(), "exit code 7");
+        runtime.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn aborted_task_reports_cancellation() {
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+
+        let task = handle.spawn(std::future::pending::<()>());
+        let abort = task.abort_handle();
+        abort.abort();
+        assert!(abort.is_aborted());
+
+        let error = runtime.block_on(task).unwrap_err();
+        assert!(error.is_cancelled());
+        assert!(!error.is_panic());
+        assert_eq!(error.to_string(), "task was cancelled");
+        assert!(error.try_into_panic().unwrap_err().is_cancelled());
+        runtime.shutdown(Duration::from_secs(1));
+    }
+
+    #[test]
+    fn completed_task_returns_output() {
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+        let task = handle.spawn(async { 42 });
+        assert_eq!(runtime.block_on(task).unwrap(), 42);
+        runtime.shutdown(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,338 @@
+//! 任务局部存储
+//! task_local! 声明的值绑定在 future 上：每次 poll 前换入线程局部变量，poll 结束后换出，
+//! 任务在不同 worker 线程上被 poll 时都能读到同一个值；
+//! 用 scope_inherited 设置的值在 spawn 子任务时会被克隆给子任务
+
+use std::cell::RefCell;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::{pin, Pin};
+use std::task::{Context, Poll};
+
+// 声明任务局部变量：
+// task_local! { pub static REQUEST_ID: u64; }
+// REQUEST_ID.scope(42, async { REQUEST_ID.get() }).await
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::task_local::LocalKey<$t> = {
+            std::thread_local! {
+                static __KEY: std::cell::RefCell<Option<$t>> = const { std::cell::RefCell::new(None) };
+            }
+            $crate::task_local::LocalKey { inner: __KEY }
+        };
+        $crate::task_local!($($rest)*);
+    };
+}
+
+pub struct LocalKey<T: 'static> {
+    // 由 task_local! 生成，不要直接使用
+    #[doc(hidden)]
+    pub inner: std::thread::LocalKey<RefCell<Option<T>>>,
+}
+
+// 当前没有处在该变量的 scope 中
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessError;
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task-local value not set")
+    }
+}
+
+impl std::error::Error for AccessError {}
+
+impl<T: 'static> LocalKey<T> {
+    // future 执行期间（包括跨 worker 线程的多次 poll）该变量的值为 value
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture { key: self, slot: Some(value), inherit: None, future: Box::pin(future) }
+    }
+
+    // 同 scope，并且 future 中 spawn 的子任务会得到该值的克隆
+    pub fn scope_inherited<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F>
+        where T: Clone + Send
+    {
+        TaskLocalFuture { key: self, slot: Some(value), inherit: Some(self), future: Box::pin(future) }
+    }
+
+    // 同步版本的 scope，f 执行期间该变量的值为 value
+    pub fn sync_scope<R>(&'static self, value: T, f: impl FnOnce() -> R) -> R {
+        let mut slot = Some(value);
+        let _guard = Swapped::enter(self, &mut slot);
+        f()
+    }
+
+    pub fn try_with<R>(&'static self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        self.inner.with(|cell| cell.borrow().as_ref().map(f).ok_or(AccessError))
+    }
+
+    // 不在 scope 中时 panic
+    #[track_caller]
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        self.try_with(f).expect("task-local value not set, access it inside LocalKey::scope")
+    }
+
+    #[track_caller]
+    pub fn get(&'static self) -> T
+        where T: Clone
+    {
+        self.with(T::clone)
+    }
+
+    fn swap(&'static self, slot: &mut Option<T>) {
+        self.inner.with(|cell| std::mem::swap(&mut *cell.borrow_mut(), slot));
+    }
+
+    fn id(&'static self) -> *const () {
+        self as *const Self as *const ()
+    }
+}
+
+// 把值换入线程局部变量，drop 时换回；poll 中发生 panic 时也会恢复，不会泄漏给同一线程上的下一个任务
+struct Swapped<'a, T: 'static> {
+    key: &'static LocalKey<T>,
+    slot: &'a mut Option<T>,
+}
+
+impl<'a, T: 'static> Swapped<'a, T> {
+    fn enter(key: &'static LocalKey<T>, slot: &'a mut Option<T>) -> Self {
+        key.swap(slot);
+        Swapped { key, slot }
+    }
+}
+
+impl<T: 'static> Drop for Swapped<'_, T> {
+    fn drop(&mut self) {
+        self.key.swap(self.slot);
+    }
+}
+
+// 能被子任务继承的变量：克隆当前线程上的值
+trait Capture: Sync {
+    fn capture(&'static self) -> Option<Box<dyn Inherited>>;
+}
+
+impl<T: Clone + Send + 'static> Capture for LocalKey<T> {
+    fn capture(&'static self) -> Option<Box<dyn Inherited>> {
+        let value = self.inner.with(|cell| cell.borrow().clone())?;
+        Some(Box::new(Captured { key: self, slot: Some(value) }))
+    }
+}
+
+// 子任务继承到的值
+trait Inherited: Send {
+    fn swap(&mut self);
+    fn id(&self) -> *const ();
+    fn key(&self) -> &'static dyn Capture;
+}
+
+struct Captured<T: 'static> {
+    key: &'static LocalKey<T>,
+    slot: Option<T>,
+}
+
+impl<T: Clone + Send + 'static> Inherited for Captured<T> {
+    fn swap(&mut self) {
+        self.key.swap(&mut self.slot);
+    }
+
+    fn id(&self) -> *const () {
+        self.key.id()
+    }
+
+    fn key(&self) -> &'static dyn Capture {
+        self.key
+    }
+}
+
+thread_local! {
+    // 当前 poll 中处于生效状态的 scope，按进入顺序排列；None 表示不向子任务传递的 scope，
+    // 它会遮住外层同一变量的可继承 scope
+    static ACTIVE: RefCell<Vec<(*const (), Option<&'static dyn Capture>)>> = const { RefCell::new(Vec::new()) };
+}
+
+// poll 期间登记在 ACTIVE 中，drop 时移除
+struct Active;
+
+impl Active {
+    fn push(id: *const (), inherit: Option<&'static dyn Capture>) -> Self {
+        ACTIVE.with(|active| active.borrow_mut().push((id, inherit)));
+        Active
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.borrow_mut().pop());
+    }
+}
+
+// LocalKey::scope 返回的 future
+pub struct TaskLocalFuture<T: 'static, F> {
+    key: &'static LocalKey<T>,
+    slot: Option<T>,
+    inherit: Option<&'static dyn Capture>,
+    future: Pin<Box<F>>,
+}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let _swapped = Swapped::enter(this.key, &mut this.slot);
+        let _active = Active::push(this.key.id(), this.inherit);
+        this.future.as_mut().poll(cx)
+    }
+}
+
+// future 已经装箱，slot 中的值只会被移动进出线程局部变量，不依赖固定地址
+impl<T: 'static, F> Unpin for TaskLocalFuture<T, F> {}
+
+// spawn 时在父任务的线程上调用：克隆所有可继承的值，子任务每次 poll 前换入
+pub(crate) fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    let mut locals: Vec<Box<dyn Inherited>> = ACTIVE.with(|active| {
+        let active = active.borrow();
+        let mut seen: Vec<*const ()> = Vec::new();
+        // 内层 scope 优先
+        active
+            .iter()
+            .rev()
+            .filter(|(id, _)| {
+                let first = !seen.contains(id);
+                seen.push(*id);
+                first
+            })
+            .filter_map(|(_, inherit)| inherit.and_then(|key| key.capture()))
+            .collect()
+    });
+
+    async move {
+        let mut future = pin!(future);
+        poll_fn(|cx| {
+            if locals.is_empty() {
+                return future.as_mut().poll(cx);
+            }
+            let _inherited = InheritedScope::enter(&mut locals);
+            future.as_mut().poll(cx)
+        })
+        .await
+    }
+}
+
+// 换入继承到的值，并把它们登记为可继承，孙任务也能继续继承
+struct InheritedScope<'a> {
+    locals: &'a mut Vec<Box<dyn Inherited>>,
+    _active: Vec<Active>,
+}
+
+impl<'a> InheritedScope<'a> {
+    fn enter(locals: &'a mut Vec<Box<dyn Inherited>>) -> Self {
+        let active = locals
+            .iter_mut()
+            .map(|local| {
+                local.swap();
+                Active::push(local.id(), Some(local.key()))
+            })
+            .collect();
+        InheritedScope { locals, _active: active }
+    }
+}
+
+impl Drop for InheritedScope<'_> {
+    fn drop(&mut self) {
+        for local in self.locals.iter_mut() {
+            local.swap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{self, Simulation};
+    use futures_lite::future::yield_now;
+
+    task_local! {
+        static REQUEST_ID: u64;
+        static USER: String;
+    }
+
+    #[test]
+    fn scope_sets_value_across_awaits() {
+        assert_eq!(REQUEST_ID.try_with(|id| *id), Err(AccessError));
+        let seen = Simulation::new(1).block_on(REQUEST_ID.scope(1, async {
+            let before = REQUEST_ID.get();
+            yield_now().await;
+            // 内层 scope 遮住外层，结束后恢复
+            let inner = REQUEST_ID.scope(2, async { REQUEST_ID.get() }).await;
+            (before, inner, REQUEST_ID.get())
+        }));
+        assert_eq!(seen, (1, 2, 1));
+        assert!(REQUEST_ID.try_with(|_| ()).is_err());
+        assert_eq!(REQUEST_ID.sync_scope(3, || REQUEST_ID.get()), 3);
+    }
+
+    #[test]
+    fn interleaved_tasks_keep_their_own_values() {
+        let seen = Simulation::new(9).block_on(async {
+            let tasks: Vec<_> = (0..4)
+                .map(|id| {
+                    sim::spawn(REQUEST_ID.scope(id, async move {
+                        let mut seen = Vec::new();
+                        for _ in 0..3 {
+                            yield_now().await;
+                            seen.push(REQUEST_ID.get());
+                        }
+                        (id, seen)
+                    }))
+                })
+                .collect();
+            let mut seen = Vec::new();
+            for task in tasks {
+                seen.push(task.await.unwrap());
+            }
+            seen
+        });
+        for (id, values) in seen {
+            assert_eq!(values, vec![id; 3]);
+        }
+    }
+
+    #[test]
+    fn inherited_scope_reaches_children_and_grandchildren() {
+        let (child, grandchild, user) = Simulation::new(1).block_on(REQUEST_ID.scope_inherited(7, async {
+            let child = sim::spawn(async {
+                let grandchild = sim::spawn(async { REQUEST_ID.get() });
+                (REQUEST_ID.get(), grandchild.await.unwrap())
+            });
+            let (child, grandchild) = child.await.unwrap();
+            let user = sim::spawn(async { USER.try_with(|user| user.clone()) }).await.unwrap();
+            (child, grandchild, user)
+        }));
+        assert_eq!((child, grandchild), (7, 7));
+        // 没有设置的变量不会被继承
+        assert_eq!(user, Err(AccessError));
+    }
+
+    #[test]
+    fn plain_scope_is_not_inherited() {
+        let (plain, shadowed) = Simulation::new(1).block_on(async {
+            let plain = REQUEST_ID
+                .scope(1, async { sim::spawn(async { REQUEST_ID.try_with(|id| *id) }).await.unwrap() })
+                .await;
+            // 不可继承的内层 scope 遮住外层可继承的 scope
+            let shadowed = REQUEST_ID
+                .scope_inherited(2, REQUEST_ID.scope(3, async {
+                    sim::spawn(async { REQUEST_ID.try_with(|id| *id) }).await.unwrap()
+                }))
+                .await;
+            (plain, shadowed)
+        });
+        assert_eq!(plain, Err(AccessError));
+        assert_eq!(shadowed, Err(AccessError));
+    }
+}
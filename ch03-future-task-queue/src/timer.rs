@@ -0,0 +1,439 @@
+//! 定时器驱动
+//! 由独立线程按截止时间排序保存 waker，到期时才唤醒任务，
+//! sleep 类 future 只在首次 poll（或 waker 变化）时登记一次，不再反复 wake_by_ref 空转；
+//! 在模拟运行时中使用没有线程的驱动，由模拟器推进虚拟时钟并触发到期的定时器
+
+use crate::multi_worker_queue::Handle;
+use crate::sim;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// 按 (截止时间, id) 排序，第一个元素就是最近到期的定时器
+type TimerKey = (Instant, u64);
+
+struct TimerState {
+    timers: BTreeMap<TimerKey, Waker>,
+    next_id: u64,
+    stopped: bool,
+}
+
+pub(crate) struct TimerDriver {
+    state: Mutex<TimerState>,
+    condvar: Condvar,
+}
+
+// 不在运行时 worker 线程上使用定时器时（例如主线程 block_on）使用的默认驱动
+static DEFAULT_TIMER: LazyLock<Arc<TimerDriver>> = LazyLock::new(|| TimerDriver::start().0);
+
+impl TimerDriver {
+    pub(crate) fn start() -> (Arc<TimerDriver>, JoinHandle<()>) {
+        let driver = Arc::new(TimerDriver {
+            state: Mutex::new(TimerState {
+                timers: BTreeMap::new(),
+                next_id: 0,
+                stopped: false,
+            }),
+            condvar: Condvar::new(),
+        });
+
+        let thread_driver = driver.clone();
+        let handle = thread::Builder::new()
+            .name("timer".to_string())
+            .spawn(move || thread_driver.run())
+            .unwrap();
+
+        (driver, handle)
+    }
+
+    // 模拟运行时使用的驱动：没有驱动线程，由模拟器调用 fire_expired
+    pub(crate) fn new_virtual() -> Arc<TimerDriver> {
+        Arc::new(TimerDriver {
+            state: Mutex::new(TimerState {
+                timers: BTreeMap::new(),
+                next_id: 0,
+                stopped: false,
+            }),
+            condvar: Condvar::new(),
+        })
+    }
+
+    // 当前模拟或运行时的定时器驱动，都不在时退回默认驱动
+    fn current() -> Arc<TimerDriver> {
+        if let Some(timer) = sim::current_timer() {
+            return timer;
+        }
+        match Handle::try_current() {
+            Some(handle) => handle.timer(),
+            None => DEFAULT_TIMER.clone(),
+        }
+    }
+
+    // 取出所有已到期的定时器
+    fn take_expired(state: &mut TimerState, now: Instant) -> Vec<Waker> {
+        let mut expired = Vec::new();
+        while let Some(entry) = state.timers.first_entry() {
+            if entry.key().0 > now {
+                break;
+            }
+            expired.push(entry.remove());
+        }
+        expired
+    }
+
+    // 按截止时间顺序唤醒 now 之前到期的定时器
+    pub(crate) fn fire_expired(&self, now: Instant) {
+        let expired = Self::take_expired(&mut self.state.lock().unwrap(), now);
+        for waker in expired {
+            waker.wake();
+        }
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.state.lock().unwrap().timers.keys().next().map(|&(deadline, _)| deadline)
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped {
+            let now = Instant::now();
+
+            // 在锁外唤醒所有已到期的定时器：waker 可能重新登记定时器或直接调度任务
+            let expired = Self::take_expired(&mut state, now);
+            if !expired.is_empty() {
+                drop(state);
+                for waker in expired {
+                    waker.wake();
+                }
+                state = self.state.lock().unwrap();
+                continue;
+            }
+
+            state = match state.timers.keys().next() {
+                Some(&(deadline, _)) => self.condvar.wait_timeout(state, deadline - now).unwrap().0,
+                None => self.condvar.wait(state).unwrap(),
+            };
+        }
+    }
+
+    // 驱动已经停止时返回 None
+    fn register(&self, deadline: Instant, waker: Waker) -> Option<TimerKey> {
+        let mut state = self.state.lock().unwrap();
+        if state.stopped {
+            return None;
+        }
+        let key = (deadline, state.next_id);
+        state.next_id += 1;
+
+        // 新定时器比当前最近的还早时，唤醒驱动线程重新计算等待时间
+        let earliest = state.timers.keys().next().is_none_or(|first| key < *first);
+        state.timers.insert(key, waker);
+        if earliest {
+            self.condvar.notify_one();
+        }
+        Some(key)
+    }
+
+    // 任务在不同 worker 之间迁移时 waker 可能变化，只在变化时替换；
+    // 返回 false 表示定时器已经触发并被移除
+    fn update(&self, key: TimerKey, waker: &Waker) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.timers.get_mut(&key) {
+            Some(registered) => {
+                if !registered.will_wake(waker) {
+                    *registered = waker.clone();
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn cancel(&self, key: TimerKey) {
+        self.state.lock().unwrap().timers.remove(&key);
+    }
+
+    // 唤醒所有尚未到期的定时器：所属运行时已停止的任务随之被取消，
+    // 在其他执行器上等待的 Sleep 被重新 poll 时改由默认驱动计时，仍按原截止时间完成
+    pub(crate) fn stop(&self) {
+        let timers = {
+            let mut state = self.state.lock().unwrap();
+            state.stopped = true;
+            self.condvar.notify_one();
+            std::mem::take(&mut state.timers)
+        };
+        for waker in timers.into_values() {
+            waker.wake();
+        }
+    }
+}
+
+// 当前时间：在模拟运行时中是虚拟时钟，否则是 Instant::now()
+pub fn now() -> Instant {
+    sim::now().unwrap_or_else(Instant::now)
+}
+
+// sleep / sleep_until 返回的 future
+pub struct Sleep {
+    deadline: Instant,
+    entry: Option<(Arc<TimerDriver>, TimerKey)>,
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(now() + duration)
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, entry: None }
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    // 修改截止时间，之前登记的定时器随之取消
+    pub fn reset(&mut self, deadline: Instant) {
+        if let Some((driver, key)) = self.entry.take() {
+            driver.cancel(key);
+        }
+        self.deadline = deadline;
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if now() >= self.deadline {
+            if let Some((driver, key)) = self.entry.take() {
+                driver.cancel(key);
+            }
+            return Poll::Ready(());
+        }
+
+        let deadline = self.deadline;
+        match &self.entry {
+            Some((driver, key)) if driver.update(*key, cx.waker()) => {},
+            _ => {
+                let driver = TimerDriver::current();
+                self.entry = match driver.register(deadline, cx.waker().clone()) {
+                    Some(key) => Some((driver, key)),
+                    // 运行时已经关闭，它的定时器驱动不再计时
+                    None => {
+                        let driver = DEFAULT_TIMER.clone();
+                        let key = driver.register(deadline, cx.waker().clone()).expect("default timer is never stopped");
+                        Some((driver, key))
+                    },
+                };
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some((driver, key)) = self.entry.take() {
+            driver.cancel(key);
+        }
+    }
+}
+
+// 按固定周期触发，第一次 tick 立即完成
+pub struct Interval {
+    period: Duration,
+    sleep: Sleep,
+}
+
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        period,
+        sleep: sleep_until(now()),
+    }
+}
+
+impl Interval {
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => {
+                let tick = self.sleep.deadline();
+                self.sleep.reset(tick + self.period);
+                Poll::Ready(tick)
+            },
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    // 返回本次 tick 的计划时间
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| self.poll_tick(cx)).await
+    }
+}
+
+// timeout 到期时返回的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+pub struct Timeout<F> {
+    future: Pin<Box<F>>,
+    sleep: Sleep,
+}
+
+// future 在 duration 内完成返回 Ok，否则丢弃 future 并返回 Err(Elapsed)
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        sleep: sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combinator::join_all;
+    use crate::commons::Runtime;
+    use futures_lite::future::{block_on, poll_once};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Wake;
+
+    // 记录是否被唤醒，唤醒时顺便读取驱动状态：在持锁时唤醒会在这里死锁
+    struct Probe {
+        driver: Arc<TimerDriver>,
+        woken: AtomicBool,
+        sender: flume::Sender<()>,
+    }
+
+    impl Wake for Probe {
+        fn wake(self: Arc<Self>) {
+            let _ = self.driver.next_deadline();
+            self.woken.store(true, Ordering::SeqCst);
+            let _ = self.sender.send(());
+        }
+    }
+
+    fn probe(driver: &Arc<TimerDriver>) -> (Arc<Probe>, flume::Receiver<()>) {
+        let (sender, receiver) = flume::unbounded();
+        (Arc::new(Probe { driver: driver.clone(), woken: AtomicBool::new(false), sender }), receiver)
+    }
+
+    #[test]
+    fn sleeps_complete_in_deadline_order() {
+        let order = Mutex::new(Vec::new());
+        let started = Instant::now();
+        block_on(join_all([60, 20, 40].map(|millis| {
+            let order = &order;
+            async move {
+                sleep(Duration::from_millis(millis)).await;
+                order.lock().unwrap().push(millis);
+            }
+        })));
+
+        assert_eq!(*order.lock().unwrap(), vec![20, 40, 60]);
+        assert!(started.elapsed() >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn driver_wakes_outside_its_lock() {
+        let (driver, thread) = TimerDriver::start();
+        let (probe, woken) = probe(&driver);
+        driver.register(Instant::now() + Duration::from_millis(10), Waker::from(probe.clone())).unwrap();
+
+        woken.recv_timeout(Duration::from_secs(2)).unwrap();
+        driver.stop();
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn interval_ticks_on_schedule() {
+        let period = Duration::from_millis(20);
+        let ticks = block_on(async {
+            let mut interval = interval(period);
+            let mut ticks = Vec::new();
+            for _ in 0..3 {
+                ticks.push(interval.tick().await);
+            }
+            ticks
+        });
+
+        assert_eq!(ticks[1] - ticks[0], period);
+        assert_eq!(ticks[2] - ticks[1], period);
+        assert!(Instant::now() >= ticks[2]);
+    }
+
+    #[test]
+    fn timeout_elapses_for_slow_future() {
+        let started = Instant::now();
+        let output = block_on(timeout(Duration::from_millis(20), std::future::pending::<()>()));
+        assert_eq!(output, Err(Elapsed));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn timeout_returns_output_of_fast_future() {
+        let started = Instant::now();
+        let output = block_on(timeout(Duration::from_secs(5), async {
+            sleep(Duration::from_millis(10)).await;
+            5
+        }));
+        assert_eq!(output, Ok(5));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stop_wakes_outstanding_timers() {
+        let (driver, thread) = TimerDriver::start();
+        let (probe, woken) = probe(&driver);
+        driver.register(Instant::now() + Duration::from_secs(60), Waker::from(probe.clone())).unwrap();
+
+        driver.stop();
+        woken.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert!(probe.woken.load(Ordering::SeqCst));
+        assert!(driver.next_deadline().is_none());
+        assert!(driver.register(Instant::now(), Waker::from(probe.clone())).is_none());
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn sleep_outlives_stopped_runtime() {
+        let mut runtime = Runtime::new();
+        let handle = runtime.run();
+        // 在运行时的驱动上登记，随后运行时关闭
+        let mut sleep = sleep(Duration::from_millis(50));
+        handle.block_on(async { assert!(poll_once(&mut sleep).await.is_none()) });
+        let deadline = sleep.deadline();
+        runtime.shutdown_now();
+
+        // 运行时的驱动已经停止，Sleep 改由默认驱动计时，仍按原截止时间完成
+        handle.block_on(sleep);
+        assert!(Instant::now() >= deadline);
+    }
+}
@@ -0,0 +1,461 @@
+//! 调度跟踪
+//! 开启 Runtime::with_trace(true) 后记录每个任务的 spawn、重新调度、每次 poll 的开始与结束以及完成事件，
+//! 事件带有所在线程，Handle::write_trace 输出为 Chrome trace-event JSON，
+//! 可以在 chrome://tracing 或 Perfetto 中查看 High / Low worker 之间的争用
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::future::{poll_fn, Future};
+use std::io::{self, BufWriter, Write};
+use std::panic::Location;
+use std::path::Path;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+use log::warn;
+
+// 事件数上限，超过后停止记录，避免长时间运行时内存无限增长
+const MAX_EVENTS: usize = 1_000_000;
+
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    // trace 中的线程编号
+    static TID: u64 = NEXT_TID.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Clone, Copy)]
+enum Phase {
+    Spawn,
+    Schedule,
+    PollStart,
+    PollEnd,
+    Complete(&'static str),
+}
+
+struct Event {
+    ts_ns: u64,
+    tid: u64,
+    task: usize,
+    phase: Phase,
+}
+
+struct TraceState {
+    events: Vec<Event>,
+    threads: HashMap<u64, String>,
+    // 任务 id -> (显示名称, spawn 调用位置)
+    tasks: HashMap<usize, (String, &'static Location<'static>)>,
+}
+
+pub(crate) struct Tracer {
+    started: Instant,
+    state: Mutex<TraceState>,
+    full: AtomicBool,
+    max_events: usize,
+}
+
+impl Tracer {
+    pub(crate) fn new() -> Self {
+        Self::with_max_events(MAX_EVENTS)
+    }
+
+    fn with_max_events(max_events: usize) -> Self {
+        Tracer {
+            started: Instant::now(),
+            state: Mutex::new(TraceState {
+                events: Vec::new(),
+                threads: HashMap::new(),
+                tasks: HashMap::new(),
+            }),
+            full: AtomicBool::new(false),
+            max_events,
+        }
+    }
+
+    fn record(&self, task: usize, phase: Phase) {
+        self.record_with(task, phase, |_| {});
+    }
+
+    // 达到事件上限后不再记录，也不再调用 update，避免任务表继续增长
+    fn record_with(&self, task: usize, phase: Phase, update: impl FnOnce(&mut TraceState)) {
+        if self.full.load(Ordering::Relaxed) {
+            return;
+        }
+        let ts_ns = self.started.elapsed().as_nanos() as u64;
+        let tid = TID.with(|tid| *tid);
+
+        let mut state = self.state.lock().unwrap();
+        if state.events.len() >= self.max_events {
+            if !self.full.swap(true, Ordering::Relaxed) {
+                warn!("trace reached {} events, further events are dropped", self.max_events);
+            }
+            return;
+        }
+        update(&mut state);
+        state.threads
+            .entry(tid)
+            .or_insert_with(|| thread::current().name().unwrap_or("<unnamed>").to_string());
+        state.events.push(Event { ts_ns, tid, task, phase });
+    }
+
+    // 记录 spawn，返回的 TaskSpan 随任务 future 一起保存，被丢弃时记录任务结束
+    pub(crate) fn spawn(self: &Arc<Self>, task: usize, name: Option<&str>, location: &'static Location<'static>) -> TaskSpan {
+        self.record_with(task, Phase::Spawn, |state| {
+            let label = match name {
+                Some(name) => format!("{} #{}", name, task),
+                None => format!("task #{}", task),
+            };
+            state.tasks.insert(task, (label, location));
+        });
+        TaskSpan { tracer: self.clone(), task, result: "cancelled" }
+    }
+
+    // waker 把任务重新放入队列
+    pub(crate) fn schedule(&self, task: usize) {
+        self.record(task, Phase::Schedule);
+    }
+
+    // 输出 Chrome trace-event 格式：任务生命周期为按 id 区分的异步事件，poll 为所在线程上的区间
+    pub(crate) fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        let mut events: Vec<String> = Vec::with_capacity(state.events.len() + state.threads.len());
+
+        let mut threads: Vec<_> = state.threads.iter().collect();
+        threads.sort();
+        for (tid, name) in threads {
+            events.push(format!(
+                r#"{{"name":"thread_name","ph":"M","pid":1,"tid":{},"args":{{"name":{}}}}}"#,
+                tid, json_string(name)
+            ));
+        }
+
+        for event in &state.events {
+            let (label, location) = state.tasks
+                .get(&event.task)
+                .map(|(label, location)| (label.as_str(), location.to_string()))
+                .unwrap_or(("task", String::new()));
+            let label = json_string(label);
+            let ts = event.ts_ns as f64 / 1000.0;
+            let common = format!(r#""pid":1,"tid":{},"ts":{:.3}"#, event.tid, ts);
+
+            let mut json = String::new();
+            let _ = match event.phase {
+                Phase::Spawn => write!(
+                    json,
+                    r#"{{"name":{},"cat":"task","ph":"b","id":{},{},"args":{{"location":{}}}}}"#,
+                    label, event.task, common, json_string(&location)
+                ),
+                Phase::Schedule => write!(
+                    json,
+                    r#"{{"name":"schedule","cat":"schedule","ph":"i","s":"t",{},"args":{{"task":{}}}}}"#,
+                    common, label
+                ),
+                Phase::PollStart => write!(json, r#"{{"name":{},"cat":"poll","ph":"B",{}}}"#, label, common),
+                Phase::PollEnd => write!(json, r#"{{"name":{},"cat":"poll","ph":"E",{}}}"#, label, common),
+                Phase::Complete(result) => write!(
+                    json,
+                    r#"{{"name":{},"cat":"task","ph":"e","id":{},{},"args":{{"result":"{}"}}}}"#,
+                    label, event.task, common, result
+                ),
+            };
+            events.push(json);
+        }
+        drop(state);
+
+        writeln!(writer, r#"{{"displayTimeUnit":"ms","traceEvents":["#)?;
+        for (index, event) in events.iter().enumerate() {
+            let separator = if index + 1 < events.len() { "," } else { "" };
+            writeln!(writer, "{}{}", event, separator)?;
+        }
+        writeln!(writer, "]}}")?;
+        writer.flush()
+    }
+
+    pub(crate) fn write_file(&self, path: &Path) -> io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+}
+
+// 任务的 trace 生命周期：在 spawn 时创建并移入任务 future，
+// 任务完成、被 abort 或随运行时关闭被丢弃（包括从未被 poll）时都会记录结束事件
+pub(crate) struct TaskSpan {
+    tracer: Arc<Tracer>,
+    task: usize,
+    result: &'static str,
+}
+
+impl TaskSpan {
+    // 包装任务 future：记录每次 poll 的开始、结束以及任务的结果
+    pub(crate) async fn instrument<F, T>(&mut self, future: F) -> thread::Result<Option<T>>
+        where F: Future<Output = thread::Result<Option<T>>>
+    {
+        let (tracer, task) = (&self.tracer, self.task);
+        let mut future = pin!(future);
+        let output = poll_fn(|cx| {
+            tracer.record(task, Phase::PollStart);
+            let poll = future.as_mut().poll(cx);
+            tracer.record(task, Phase::PollEnd);
+            poll
+        })
+        .await;
+
+        self.result = match &output {
+            Ok(Some(_)) => "completed",
+            Ok(None) => "cancelled",
+            Err(_) => "panicked",
+        };
+        output
+    }
+}
+
+impl Drop for TaskSpan {
+    fn drop(&mut self) {
+        self.tracer.record(self.task, Phase::Complete(self.result));
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            },
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commons::Runtime;
+    use crate::multi_worker_queue::Builder;
+    use crate::timer;
+    use std::time::Duration;
+
+    // 测试用的最小 JSON 解析器：只覆盖 write 的输出会用到的语法，解析失败直接 panic
+    #[derive(Debug, Clone, PartialEq)]
+    enum Json {
+        Number(f64),
+        Str(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> Option<&Json> {
+            match self {
+                Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+                _ => None,
+            }
+        }
+
+        fn str(&self, key: &str) -> &str {
+            match self.get(key) {
+                Some(Json::Str(value)) => value,
+                other => panic!("{} is not a string: {:?}", key, other),
+            }
+        }
+
+        fn num(&self, key: &str) -> f64 {
+            match self.get(key) {
+                Some(Json::Number(value)) => *value,
+                other => panic!("{} is not a number: {:?}", key, other),
+            }
+        }
+    }
+
+    struct Parser<'a> {
+        chars: std::iter::Peekable<std::str::Chars<'a>>,
+    }
+
+    impl Parser<'_> {
+        fn parse(text: &str) -> Json {
+            let mut parser = Parser { chars: text.chars().peekable() };
+            let value = parser.value();
+            parser.skip_whitespace();
+            assert!(parser.chars.next().is_none(), "trailing characters after JSON value");
+            value
+        }
+
+        fn skip_whitespace(&mut self) {
+            while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        }
+
+        fn expect(&mut self, expected: char) {
+            self.skip_whitespace();
+            assert_eq!(self.chars.next(), Some(expected));
+        }
+
+        fn value(&mut self) -> Json {
+            self.skip_whitespace();
+            match self.chars.peek() {
+                Some('{') => {
+                    self.chars.next();
+                    let mut fields = Vec::new();
+                    self.skip_whitespace();
+                    if self.chars.next_if_eq(&'}').is_none() {
+                        loop {
+                            self.skip_whitespace();
+                            let key = self.string();
+                            self.expect(':');
+                            fields.push((key, self.value()));
+                            self.skip_whitespace();
+                            match self.chars.next() {
+                                Some(',') => continue,
+                                Some('}') => break,
+                                other => panic!("expected , or }} in object, found {:?}", other),
+                            }
+                        }
+                    }
+                    Json::Object(fields)
+                },
+                Some('[') => {
+                    self.chars.next();
+                    let mut items = Vec::new();
+                    self.skip_whitespace();
+                    if self.chars.next_if_eq(&']').is_none() {
+                        loop {
+                            items.push(self.value());
+                            self.skip_whitespace();
+                            match self.chars.next() {
+                                Some(',') => continue,
+                                Some(']') => break,
+                                other => panic!("expected , or ] in array, found {:?}", other),
+                            }
+                        }
+                    }
+                    Json::Array(items)
+                },
+                Some('"') => Json::Str(self.string()),
+                _ => {
+                    let mut number = String::new();
+                    while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                        number.push(c);
+                    }
+                    Json::Number(number.parse().unwrap_or_else(|_| panic!("invalid number {:?}", number)))
+                },
+            }
+        }
+
+        fn string(&mut self) -> String {
+            assert_eq!(self.chars.next(), Some('"'));
+            let mut value = String::new();
+            loop {
+                match self.chars.next().expect("unterminated string") {
+                    '"' => return value,
+                    '\\' => match self.chars.next().expect("unterminated escape") {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        'u' => {
+                            let code: String = (0..4).map(|_| self.chars.next().unwrap()).collect();
+                            value.push(char::from_u32(u32::from_str_radix(&code, 16).unwrap()).unwrap());
+                        },
+                        c => value.push(c),
+                    },
+                    c if (c as u32) < 0x20 => panic!("unescaped control character in string"),
+                    c => value.push(c),
+                }
+            }
+        }
+    }
+
+    fn events(output: &str) -> Vec<Json> {
+        match Parser::parse(output).get("traceEvents") {
+            Some(Json::Array(events)) => events.clone(),
+            other => panic!("traceEvents is not an array: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn trace_is_valid_json_with_paired_task_events() {
+        let mut runtime = Runtime::new().with_trace(true);
+        let handle = runtime.run();
+
+        let sleeper = handle.spawn(async {
+            for _ in 0..3 {
+                timer::sleep(Duration::from_millis(5)).await;
+            }
+        });
+        // 名称中需要转义的字符不能破坏输出
+        let quoted = Builder::new().name("say \"hi\"\n").spawn_on(async { 1 }, &handle);
+        let quoted_id = quoted.id();
+        let panicked = handle.spawn(async { panic!("traced") });
+        runtime.block_on(sleeper).unwrap();
+        assert_eq!(runtime.block_on(quoted).unwrap(), 1);
+        assert!(runtime.block_on(panicked).is_err());
+        // 从未完成的任务在关闭时被丢弃，同样记录结束事件
+        let _stuck = handle.spawn(std::future::pending::<()>());
+        runtime.shutdown(Duration::from_millis(50));
+
+        let path = std::env::temp_dir().join(format!("ch03-trace-{}.json", std::process::id()));
+        handle.write_trace(&path).unwrap();
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events = events(&output);
+        let mut spans: HashMap<u64, (usize, Vec<String>)> = HashMap::new();
+        let mut depth: HashMap<u64, i64> = HashMap::new();
+        for event in &events {
+            match event.str("ph") {
+                "b" => spans.entry(event.num("id") as u64).or_default().0 += 1,
+                "e" => {
+                    let result = event.get("args").unwrap().str("result").to_string();
+                    spans.entry(event.num("id") as u64).or_default().1.push(result);
+                },
+                "B" => *depth.entry(event.num("tid") as u64).or_default() += 1,
+                "E" => {
+                    let depth = depth.entry(event.num("tid") as u64).or_default();
+                    *depth -= 1;
+                    assert!(*depth >= 0, "poll end without start");
+                },
+                _ => {},
+            }
+        }
+
+        // 每个任务恰好一对 b/e，poll 的开始与结束在各线程上配对
+        assert_eq!(spans.len(), 4);
+        for (id, (begins, ends)) in &spans {
+            assert_eq!((*begins, ends.len()), (1, 1), "task {} is not paired", id);
+        }
+        assert!(depth.values().all(|depth| *depth == 0));
+        let mut results: Vec<_> = spans.values().map(|(_, ends)| ends[0].as_str()).collect();
+        results.sort();
+        assert_eq!(results, vec!["cancelled", "completed", "completed", "panicked"]);
+        let quoted = events.iter().find(|event| event.str("ph") == "b" && event.num("id") as usize == quoted_id).unwrap();
+        assert_eq!(quoted.str("name"), format!("say \"hi\"\n #{}", quoted_id));
+    }
+
+    #[test]
+    fn trace_stops_recording_at_event_cap() {
+        let tracer = Arc::new(Tracer::with_max_events(10));
+        let spans: Vec<_> = (0..8)
+            .map(|task| {
+                let span = tracer.spawn(task, None, Location::caller());
+                tracer.schedule(task);
+                span
+            })
+            .collect();
+        drop(spans);
+
+        let mut output = Vec::new();
+        tracer.write(&mut output).unwrap();
+        let events = events(&String::from_utf8(output).unwrap());
+        let recorded = events.iter().filter(|event| event.str("ph") != "M").count();
+        assert_eq!(recorded, 10);
+        // 超过上限后 spawn 的任务不再进入任务表
+        assert_eq!(tracer.state.lock().unwrap().tasks.len(), 5);
+    }
+}
@@ -0,0 +1,318 @@
+//! 工作窃取（work-stealing）调度
+//! 每个 worker 拥有本地双端队列，新任务进入全局注入队列，
+//! worker 自己的 waker 重新调度的任务留在本地，空闲 worker 从兄弟 worker 窃取
+
+use crate::multi_worker_queue::{WorkerContext, WorkerSignal};
+use async_task::Runnable;
+use crossbeam_deque::{Injector, Stealer, Worker};
+use std::cell::{Cell, OnceCell};
+use std::iter;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// 区分同一进程中的多个运行时，避免把任务推入其他运行时 worker 的本地队列
+static NEXT_QUEUE_ID: AtomicUsize = AtomicUsize::new(0);
+
+// 窃取队列无法查看任务的排队时间，HIGH worker 每取这么多次任务就从最低级开始取一次，
+// 保证低优先级任务在高优先级任务持续涌入时也能推进
+const FAIR_INTERVAL: u32 = 31;
+
+// worker 线程私有的本地队列（每个优先级一个），通过线程局部变量让 schedule 闭包找到当前 worker
+struct LocalQueues {
+    queue_id: usize,
+    is_high: bool,
+    levels: Vec<Worker<Runnable>>,
+    ticks: Cell<u32>,
+}
+
+thread_local! {
+    static LOCAL: OnceCell<LocalQueues> = const { OnceCell::new() };
+}
+
+// 所有 worker 共享的部分：按优先级划分的全局注入队列和各 worker 本地队列的 Stealer
+pub(crate) struct StealingQueue {
+    id: usize,
+    injectors: Vec<Injector<Runnable>>,
+    // stealers[level][worker]
+    stealers: Vec<Vec<Stealer<Runnable>>>,
+    // 本地队列在运行时创建时生成，worker 线程启动后按下标取走
+    locals: Mutex<Vec<Option<LocalQueues>>>,
+    high_signal: WorkerSignal,
+    low_signal: WorkerSignal,
+}
+
+// 先取本地队列，再从全局注入队列批量窃取，最后从兄弟 worker 窃取
+fn find_task(local: &Worker<Runnable>, injector: &Injector<Runnable>, stealers: &[Stealer<Runnable>]) -> Option<Runnable> {
+    local.pop().or_else(|| {
+        iter::repeat_with(|| {
+            injector
+                .steal_batch_and_pop(local)
+                .or_else(|| stealers.iter().map(|s| s.steal()).collect())
+        })
+        .find(|s| !s.is_retry())
+        .and_then(|s| s.success())
+    })
+}
+
+impl StealingQueue {
+    // HIGH worker 在前，LOW worker 在后；LOW worker 只处理最低级，其他级别的本地队列只用于被窃取
+    pub(crate) fn new(high_num: usize, low_num: usize, levels: usize) -> Self {
+        let id = NEXT_QUEUE_ID.fetch_add(1, Ordering::Relaxed);
+        let levels = levels.max(1);
+        let locals: Vec<LocalQueues> = (0..high_num + low_num)
+            .map(|index| LocalQueues {
+                queue_id: id,
+                is_high: index < high_num,
+                levels: (0..levels).map(|_| Worker::new_fifo()).collect(),
+                ticks: Cell::new(0),
+            })
+            .collect();
+
+        StealingQueue {
+            id,
+            injectors: (0..levels).map(|_| Injector::new()).collect(),
+            stealers: (0..levels)
+                .map(|level| locals.iter().map(|l| l.levels[level].stealer()).collect())
+                .collect(),
+            locals: Mutex::new(locals.into_iter().map(Some).collect()),
+            high_signal: WorkerSignal::new(),
+            low_signal: WorkerSignal::new(),
+        }
+    }
+
+    pub(crate) fn levels(&self) -> usize {
+        self.injectors.len()
+    }
+
+    fn lowest(&self) -> usize {
+        self.injectors.len() - 1
+    }
+
+    fn notify(&self, level: usize) {
+        // 与 PriorityQueue 一致：最低级任务也可以由空闲的 HIGH worker 处理
+        if level == self.lowest() {
+            self.low_signal.notify_one();
+        }
+        self.high_signal.notify_one();
+    }
+
+    fn has_task(&self, level: usize) -> bool {
+        !self.injectors[level].is_empty() || self.stealers[level].iter().any(|s| !s.is_empty())
+    }
+
+    pub(crate) fn schedule(&self, runnable: Runnable, level: usize) {
+        // 当前线程是本运行时的 worker：任务留在本地队列；本地已有积压，
+        // 或当前 LOW worker 无法处理该级别的任务时，再唤醒兄弟 worker 来窃取
+        let runnable = LOCAL.with(|cell| match cell.get() {
+            Some(local) if local.queue_id == self.id => {
+                let queue = &local.levels[level];
+                let need_help = !queue.is_empty() || (!local.is_high && level != self.lowest());
+                queue.push(runnable);
+                if need_help {
+                    self.notify(level);
+                }
+                None
+            },
+            _ => Some(runnable),
+        });
+
+        // 非 worker 线程（首次 spawn 或外部唤醒）：进入全局注入队列
+        if let Some(runnable) = runnable {
+            self.injectors[level].push(runnable);
+            self.notify(level);
+        }
+    }
+
+    // 按优先级从高到低查找；HIGH worker 每 FAIR_INTERVAL 次改为从低到高
+    fn next_task(&self, local: &LocalQueues) -> Option<Runnable> {
+        let find = |level: usize| find_task(&local.levels[level], &self.injectors[level], &self.stealers[level]);
+        if !local.is_high {
+            return find(self.lowest());
+        }
+
+        let ticks = local.ticks.get().wrapping_add(1);
+        local.ticks.set(ticks);
+        if ticks.is_multiple_of(FAIR_INTERVAL) {
+            (0..self.levels()).rev().find_map(find)
+        } else {
+            (0..self.levels()).find_map(find)
+        }
+    }
+
+    // 非 worker 线程没有本地队列：按优先级从全局注入队列和各 worker 本地队列各取一个
+    pub(crate) fn steal(&self) -> Option<Runnable> {
+        (0..self.levels()).find_map(|level| {
+            iter::repeat_with(|| {
+                self.injectors[level]
+                    .steal()
+                    .or_else(|| self.stealers[level].iter().map(|s| s.steal()).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        })
+    }
+
+    pub(crate) fn run_worker(&self, worker: &WorkerContext<'_>) {
+        let local = self.locals.lock().unwrap()[worker.index()].take().unwrap();
+
+        LOCAL.with(|cell| {
+            let _ = cell.set(local);
+            let local = cell.get().unwrap();
+            while !worker.is_stopped() {
+                match self.next_task(local) {
+                    Some(runnable) => worker.run(runnable),
+                    None if local.is_high => worker.park(|| self.high_signal.wait_while(|| {
+                        !worker.is_stopped() && !(0..self.levels()).any(|level| self.has_task(level))
+                    })),
+                    None => worker.park(|| self.low_signal.wait_while(|| {
+                        !worker.is_stopped() && !self.has_task(self.lowest())
+                    })),
+                }
+            }
+
+            // 退出前丢弃本地队列中剩余的 Runnable
+            for queue in &local.levels {
+                while queue.pop().is_some() {}
+            }
+        });
+    }
+
+    pub(crate) fn wake_all(&self) {
+        self.high_signal.notify_all();
+        self.low_signal.notify_all();
+    }
+
+    // 丢弃全局注入队列中剩余的 Runnable，对应的任务随之被取消
+    pub(crate) fn drain(&self) {
+        for injector in &self.injectors {
+            while !injector.steal().is_empty() {}
+        }
+    }
+
+    // 全局注入队列与各 worker 本地队列中的任务数之和
+    pub(crate) fn len(&self, level: usize) -> usize {
+        self.injectors[level].len() + self.stealers[level].iter().map(Stealer::len).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commons::Runtime;
+    use crate::multi_worker_queue::Handle;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    // 执行时把 tag 记入 order 的 Runnable
+    fn tagged(tag: usize, order: &Arc<Mutex<Vec<usize>>>) -> Runnable {
+        let order = order.clone();
+        let (runnable, task) = async_task::spawn(async move { order.lock().unwrap().push(tag) }, |_| {});
+        task.detach();
+        runnable
+    }
+
+    #[test]
+    fn idle_worker_steals_from_busy_local_queue() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let busy = Worker::new_fifo();
+        let idle = Worker::new_fifo();
+        let injector = Injector::new();
+        for tag in 0..3 {
+            busy.push(tagged(tag, &order));
+        }
+
+        // 本地队列和注入队列都为空，只能从兄弟 worker 窃取
+        let stealers = vec![busy.stealer()];
+        while let Some(runnable) = find_task(&idle, &injector, &stealers) {
+            runnable.run();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+        assert!(busy.is_empty());
+    }
+
+    #[test]
+    fn injector_is_drained_before_stealing() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let local = Worker::new_fifo();
+        let sibling = Worker::new_fifo();
+        let injector = Injector::new();
+        sibling.push(tagged(9, &order));
+        for tag in 0..4 {
+            injector.push(tagged(tag, &order));
+        }
+
+        let stealers = vec![sibling.stealer()];
+        while let Some(runnable) = find_task(&local, &injector, &stealers) {
+            runnable.run();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2, 3, 9]);
+        assert!(injector.is_empty());
+    }
+
+    #[test]
+    fn worker_keeps_rescheduled_tasks_local() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let queue = StealingQueue::new(2, 1, 2);
+        let local = queue.locals.lock().unwrap()[0].take().unwrap();
+        LOCAL.with(|cell| {
+            let _ = cell.set(local);
+        });
+
+        // 当前线程是 worker 0：任务进入它的本地队列，其他线程可以窃取
+        queue.schedule(tagged(1, &order), 0);
+        assert!(queue.injectors[0].is_empty());
+        assert_eq!(queue.stealers[0][0].len(), 1);
+        assert_eq!(queue.len(0), 1);
+
+        // 其他运行时的 worker 被视为外部线程，任务进入全局注入队列
+        let other = StealingQueue::new(2, 1, 2);
+        other.schedule(tagged(2, &order), 1);
+        assert_eq!(other.injectors[1].len(), 1);
+
+        // 非 worker 线程按优先级从注入队列和本地队列取任务
+        let outside = &queue;
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                outside.schedule(tagged(3, &order), 1);
+                while let Some(runnable) = outside.steal() {
+                    runnable.run();
+                }
+            });
+        });
+        assert_eq!(*order.lock().unwrap(), vec![1, 3]);
+        other.drain();
+        assert_eq!(other.len(1), 0);
+    }
+
+    fn worker_name() -> String {
+        thread::current().name().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn children_of_blocked_worker_are_stolen() {
+        let mut runtime = Runtime::new().with_work_stealing(true).with_high_num(2).with_low_num(1);
+        let handle = runtime.run();
+
+        let parent = handle.spawn(async {
+            let parent = worker_name();
+            // 子任务留在本 worker 的本地队列，父任务随后阻塞住这个 worker，只能由其他 worker 窃取执行
+            let (done_tx, done_rx) = flume::unbounded();
+            let children: Vec<_> = (0..4)
+                .map(|_| {
+                    let done_tx = done_tx.clone();
+                    Handle::current().spawn(async move { done_tx.send(worker_name()).unwrap() })
+                })
+                .collect();
+            let ran_on: Vec<String> = (0..4)
+                .map(|_| done_rx.recv_timeout(Duration::from_secs(5)).unwrap())
+                .collect();
+            drop(children);
+            (parent, ran_on)
+        });
+
+        let (parent, ran_on) = runtime.block_on(parent).unwrap();
+        assert!(ran_on.iter().all(|name| *name != parent), "{} ran its own children: {:?}", parent, ran_on);
+        runtime.shutdown(Duration::from_secs(1));
+    }
+}
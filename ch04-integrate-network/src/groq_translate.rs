@@ -3,6 +3,7 @@
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use pyo3_ffi::c_str;
+use rustom_runtime::blocking::spawn_blocking;
 use std::ffi::CString;
 
 pub fn translate(source_text: &str, source_lang: &str, target_lang: &str, country: &str, api_key: &str) -> String {
@@ -35,6 +36,18 @@ pub fn translate(source_text: &str, source_lang: &str, target_lang: &str, countr
     return result;
 }
 
+// 调用 Python 的翻译会阻塞数秒，在运行时中使用时交给阻塞线程池，不占住 worker 线程
+pub async fn translate_async(source_text: String, source_lang: String, target_lang: String, country: String, api_key: String) -> String {
+    let result = spawn_blocking(move || translate(&source_text, &source_lang, &target_lang, &country, &api_key)).await;
+    match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Translate Error: {:?}", err);
+            String::new()
+        },
+    }
+}
+
 fn print_separator_lines(num_lines: usize) {
     for _ in 0..num_lines {
         print!("------------------------");
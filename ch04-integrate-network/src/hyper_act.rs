@@ -9,22 +9,30 @@ use async_native_tls::TlsStream;
 use smol::{io, prelude::*, Async};
 use std::future::Future;
 use tower_service::Service;
-use smol::future;
 use http_body_util::Empty;
 use bytes::Bytes;
-use rustom_runtime::{commons::{FutureType, Runtime}, spawn_task_macro};
+use rustom_runtime::{commons::Runtime, multi_worker_queue::Handle, spawn_task_macro};
 
 use hyper::{Request, Response};
 use hyper_util::client::legacy::Client;
 
 use hyper::body::Incoming;
 
+// 持有运行时句柄：hyper 可能在非 worker 线程上调用 execute，不能依赖 Handle::current()
 #[derive(Clone)]
-pub struct CustomExecutor;
+pub struct CustomExecutor {
+    handle: Handle,
+}
+
+impl CustomExecutor {
+    pub fn new(handle: Handle) -> Self {
+        CustomExecutor { handle }
+    }
+}
 
 impl <F: Future + Send + 'static> hyper::rt::Executor<F> for CustomExecutor {
     fn execute(&self, fut: F) {
-        spawn_task_macro!(async {
+        spawn_task_macro!(self.handle, async {
             println!("sending request");
             fut.await;
         }).detach();
@@ -208,8 +216,8 @@ impl Connection for CustormStream {
     }
 }
 
-pub async fn fetch(req: Request<Empty<Bytes>>) -> Result<Response<Incoming>> {
-    let client = Client::builder(CustomExecutor)
+pub async fn fetch(handle: Handle, req: Request<Empty<Bytes>>) -> Result<Response<Incoming>> {
+    let client = Client::builder(CustomExecutor::new(handle))
         .build::<CustomConnector, Empty<Bytes>>(CustomConnector);
 
     let response = client.request(req).await?;
@@ -217,13 +225,16 @@ pub async fn fetch(req: Request<Empty<Bytes>>) -> Result<Response<Incoming>> {
 }
 
 pub fn start() -> Result<(), Box<dyn std::error::Error>> {
-    Runtime::new().with_low_num(2).with_high_num(4).run();
+    // Runtime 被丢弃时关闭运行时，需要持有到请求完成
+    let mut runtime = Runtime::new().with_low_num(2).with_high_num(4);
+    let handle = runtime.run();
 
-    let future = async {
+    let client_handle = handle.clone();
+    let future = async move {
         
         let req = Request::get("http://www.baidu.com").body(Empty::<Bytes>::new()).unwrap();
 
-        let response = fetch(req).await.unwrap();
+        let response = fetch(client_handle, req).await.unwrap();
 
         use http_body_util::BodyExt;
         // 调用 collect() 时需要使用 http_body_util 中的 BodyExt
@@ -234,8 +245,8 @@ pub fn start() -> Result<(), Box<dyn std::error::Error>> {
         println!("{}", html);
     };
 
-    let test = spawn_task_macro!(future);
-    let _outcome = future::block_on(test);
+    let test = spawn_task_macro!(handle, future);
+    let _outcome = handle.block_on(test);
 
     Ok(())
 }
\ No newline at end of file
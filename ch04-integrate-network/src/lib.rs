@@ -10,11 +10,11 @@ mod twitter_ntscraper;
 pub use hyper_act::{CustomConnector, CustomExecutor, start as hyper_start};
 pub use mio_act::start as mio_start;
 pub use srt_fmt::start as srt_fmt_start;
-pub use semantic::start as semantic_start;
+pub use semantic::{start as semantic_start, start_async as semantic_start_async};
 
 pub use config::{
     dotenv::{config as dotenv_config, get_groq_key as get_groq_key_by_dotenv}, 
     toml::{config as toml_config, get_groq_key as get_groq_key_by_toml}
 };
 
-pub use groq_translate::translate as translate_start;
\ No newline at end of file
+pub use groq_translate::{translate as translate_start, translate_async as translate_start_async};
\ No newline at end of file
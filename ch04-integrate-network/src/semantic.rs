@@ -93,6 +93,15 @@
 
 
 use rust_bert::pipelines::translation::{Language, TranslationModelBuilder};
+use rustom_runtime::blocking::spawn_blocking;
+
+// 加载模型和推理都是阻塞的 CPU 密集操作，在运行时中使用时交给阻塞线程池
+pub async fn start_async(content: String, source: Language, target: Language) -> anyhow::Result<String> {
+    spawn_blocking(move || start(&content, source, target))
+        .await
+        .map_err(|err| anyhow::anyhow!("translation task failed: {}", err))?
+}
+
 pub fn start(content: &str, source: Language, target: Language) -> anyhow::Result<String> {
     let model = TranslationModelBuilder::new()
         .with_source_languages(vec![Language::English, Language::ChineseMandarin])
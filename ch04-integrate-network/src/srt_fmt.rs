@@ -1,7 +1,9 @@
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
 use rust_bert::pipelines::translation::Language;
-use crate::{semantic_start, translate_start, get_groq_key_by_dotenv, get_groq_key_by_toml};
+use rustom_runtime::commons::Runtime;
+use crate::{semantic_start, translate_start_async, get_groq_key_by_dotenv, get_groq_key_by_toml};
 
 
 #[derive(Debug)]
@@ -103,14 +105,18 @@ fn process_srt_file(input_path: &str, output_path: &str) -> anyhow::Result<()> {
     let target_lang = "Chinese";
     let country = "China";
 
-    // 翻译
-    for subtitle in &mut subtitles {
-        // subtitle.transed_content = semantic_start(&subtitle.content, Language::English, Language::ChineseMandarin)?;
-        // 翻译
-        let result = translate_start(&subtitle.content, source_lang, target_lang, country,
-            get_groq_key_by_dotenv().unwrap_or(get_groq_key_by_toml().expect("Failed to get GROQ API key")).as_str());
-        subtitle.transed_content = result;
-    }
+    let api_key = get_groq_key_by_dotenv().unwrap_or(get_groq_key_by_toml().expect("Failed to get GROQ API key"));
+
+    // 翻译：阻塞的 Python 调用经由 spawn_blocking 执行，不占住运行时的 worker 线程
+    let mut runtime = Runtime::new();
+    runtime.block_on(async {
+        for subtitle in &mut subtitles {
+            // subtitle.transed_content = semantic_start_async(subtitle.content.clone(), Language::English, Language::ChineseMandarin).await?;
+            subtitle.transed_content = translate_start_async(subtitle.content.clone(), source_lang.to_string(),
+                target_lang.to_string(), country.to_string(), api_key.clone()).await;
+        }
+    });
+    runtime.shutdown(Duration::from_secs(1));
 
     // println!("{:?}", subtitles);
 